@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use libp2p::PeerId;
+use muse::fuzzing::{decode_chunk, ChunkCollector, ChunkOutcome};
+
+// Feeds a stream of chunks from a handful of peers into one collector.
+// The input is split into length prefixed chunk encodings.
+fuzz_target!(|data: &[u8]| {
+    let peers = [PeerId::random(), PeerId::random(), PeerId::random()];
+    let mut collector = ChunkCollector::new();
+
+    let mut rest = data;
+    while let [peer, length, tail @ ..] = rest {
+        let length = (*length as usize).min(tail.len());
+        let (encoded, tail) = tail.split_at(length);
+        rest = tail;
+
+        if let Ok(chunk) = decode_chunk(encoded) {
+            let peer = peers[*peer as usize % peers.len()];
+            if let ChunkOutcome::Complete(message) = collector.add_chunk(peer, chunk) {
+                assert!(message.len() <= muse::fuzzing::MAX_MESSAGE_SIZE);
+            }
+        }
+    }
+});
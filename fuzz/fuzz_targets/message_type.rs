@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use muse::fuzzing::decode_message;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decode_message(data);
+});
@@ -0,0 +1,164 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path};
+
+use crate::canvas_state_sync::{
+    crdt::BoardPin, roles::Roster, rooms::Room, sync_types::SyncableState,
+};
+
+pub type ItemId = u64;
+
+pub fn new_item_id() -> ItemId {
+    rand::random()
+}
+
+/// Tags and a free-text description, found by the search bar.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct ItemLabels {
+    pub tags: Vec<String>,
+    pub description: String,
+}
+
+/// What the board knows about an image besides its bytes.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct ImageInfo {
+    pub file_name: Option<String>, // Without the directory, which stays with the importer
+    pub imported_at_ms: u64,
+    pub content_hash: String, // SHA-256 of the original bytes
+    pub source_url: String,   // Where the image was found, for attribution
+    pub labels: ItemLabels,
+}
+
+/// Text note placed on the canvas. Position is in canvas space.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct TextItem {
+    pub id: ItemId,
+    pub text: String,
+    pub position: [f32; 2],
+    pub scale: f32,
+    pub font_size: f32,
+    pub color: [u8; 4],
+    pub background: Option<[u8; 4]>,
+    pub labels: ItemLabels,
+}
+
+impl TextItem {
+    pub fn new(position: [f32; 2]) -> Self {
+        Self {
+            id: new_item_id(),
+            text: "Text".to_owned(),
+            position,
+            scale: 1.0,
+            font_size: 16.0,
+            color: [255, 255, 255, 255],
+            background: Some([40, 40, 40, 220]),
+            labels: ItemLabels::default(),
+        }
+    }
+}
+
+/// Freehand ink stroke. Points are in canvas space, or relative to
+/// the top left corner of the image it is attached to.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct InkStroke {
+    pub id: ItemId,
+    pub points: Vec<[f32; 2]>,
+    pub width: f32,
+    pub color: [u8; 4],
+    pub highlighter: bool,
+    pub attached_to: Option<ItemId>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShapeKind {
+    Arrow,
+    Line,
+    Rectangle,
+    Ellipse,
+}
+
+/// End point of a shape. Lines and arrows can attach to items and follow them around.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum ShapeAnchor {
+    Point([f32; 2]),
+    Image(ItemId),
+    Text(ItemId),
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ShapeItem {
+    pub id: ItemId,
+    pub kind: ShapeKind,
+    pub start: ShapeAnchor,
+    pub end: ShapeAnchor,
+    pub width: f32,
+    pub color: [u8; 4],
+}
+
+/// Row of colour swatches, e.g. a palette extracted from an image.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SwatchItem {
+    pub id: ItemId,
+    pub position: [f32; 2],
+    pub colors: Vec<[u8; 3]>,
+    pub labels: ItemLabels,
+}
+
+/// Comment thread pinned to a spot on an image.
+/// The anchor is relative to the image size, so it stays on the same detail when the image is scaled.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CommentPin {
+    pub id: ItemId,
+    pub image: ItemId,
+    pub anchor: [f32; 2],
+    pub resolved: bool,
+    pub comments: Vec<Comment>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct Comment {
+    pub id: ItemId,
+    pub author: String, // Peer ID of the author
+    pub timestamp_ms: u64,
+    pub text: String,
+}
+
+impl CommentPin {
+    /// Pin as shown, built from the synced one. Replies are sorted by time.
+    pub fn from_board(id: ItemId, pin: &BoardPin) -> Self {
+        let mut comments: Vec<Comment> = pin
+            .comments
+            .values()
+            .map(|comment| comment.value.clone())
+            .collect();
+        comments.sort_by_key(|comment| (comment.timestamp_ms, comment.id));
+        Self {
+            id,
+            image: pin.image.value,
+            anchor: pin.anchor.value,
+            resolved: pin.resolved.value,
+            comments,
+        }
+    }
+}
+
+/// Saved board. Peers fetch image bytes separately, but a file has to carry them.
+#[derive(Serialize, Deserialize)]
+pub struct BoardFile {
+    pub state: SyncableState,
+    pub blobs: HashMap<String, Vec<u8>>, // Image bytes keyed by content hash
+    pub room: Option<Room>,              // Room the board is synced in, rejoined on open
+    pub roster: Roster,                  // Roles in the room, kept by its host
+    pub source_paths: HashMap<ItemId, String>, // Of images imported here, never synced
+}
+
+pub fn save_board(path: &Path, file: &BoardFile) -> Result<()> {
+    let bytes = bincode::serialize(file)?;
+    std::fs::write(path, bytes)?;
+    Ok(())
+}
+
+pub fn load_board(path: &Path) -> Result<BoardFile> {
+    let bytes = std::fs::read(path)?;
+    Ok(bincode::deserialize(&bytes)?)
+}
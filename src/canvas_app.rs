@@ -1,17 +1,44 @@
 use crate::{
+    board::{
+        load_board, new_item_id, save_board, BoardFile, Comment, CommentPin, ImageInfo, InkStroke,
+        ItemId, ItemLabels, ShapeAnchor, ShapeItem, ShapeKind, SwatchItem, TextItem,
+    },
     canvas_state_sync::{
+        blobs::BlobStore,
+        crdt::{ActorId, Board, Item, OperationKind, Reorder},
+        crypto::OpenError,
+        identity::{fingerprint, Identity},
+        invite::{load_known_peers, save_known_peers, Invite, KnownPeer},
         p2p,
-        sync_types::{MessageType, SyncableState},
+        peers::PeerInfo,
+        roles::{Role, Roster},
+        rooms::{normalize_code, Room},
+        sync_types::{
+            MessageType, P2pCommand, P2pEvent, PlaybackEvent, Presence, SyncableState,
+            TransferEvent,
+        },
     },
     custom_widgets::{
-        canvas_image::{canvas_image, CanvasImageData},
+        canvas_image::{canvas_image, overlay_image, CanvasImageData, ImageOverlays},
+        canvas_swatch::{canvas_swatch, swatch_index_at},
+        canvas_text::canvas_text,
         toggle::toggle,
     },
+    decode::spawn_decode_corrected,
+    eyedropper::{paint_loupe, source_pixel, SourcePixels, HISTORY_LENGTH},
+    frame_export::{has_animation, spawn_export, ExportEvent, ExportFormat, ExportRequest},
+    ink::{canvas_points, hits, paint_stroke, InkEdit},
+    metadata::{format_file_size, format_utc, orientation_name, read_exif, ExifSummary},
+    palette::{color_descriptions, spawn_extract_palette},
+    playback::{frame_at, frame_start_ms, Playback},
+    search::{Query, Search, SearchHit, SearchMode, Searchable},
+    shapes::{paint_shape, resolve_endpoints, AnchorTargets},
 };
 use anyhow::{Ok, Result};
-use eframe::egui::{self, include_image, Grid, SidePanel, TopBottomPanel, Widget};
+use eframe::egui::{self, Grid, SidePanel, TopBottomPanel, Widget};
 use egui::emath::TSTransform;
 use std::{
+    collections::{HashMap, HashSet, VecDeque},
     io::Read,
     path::PathBuf,
     sync::{
@@ -27,25 +54,203 @@ use tokio::sync::mpsc;
 pub struct App {
     pub transform: TSTransform,
     pub images: Vec<CanvasImageData>,
-    pub dropped_bytes: Vec<Vec<u8>>,
-    pub file_loader_channel: Option<std::sync::mpsc::Receiver<Vec<u8>>>,
+    pub board: Board,
+    pub blobs: BlobStore,
+    pub actor: ActorId,
+    pub unsent_items: HashSet<ItemId>, // Edited during a drag or while typing, sent once it ends
+    pub texts: Vec<TextItem>,
+    pub editing_text: Option<u64>,
+    pub file_loader_channel: Option<std::sync::mpsc::Receiver<(Vec<u8>, PathBuf)>>,
 
     // p2p communication fields
-    pub p2p_receiver: Option<mpsc::Receiver<MessageType>>,
-    pub gui_sender: Option<mpsc::Sender<MessageType>>,
+    pub p2p_receiver: Option<mpsc::Receiver<P2pEvent>>,
+    pub gui_sender: Option<mpsc::Sender<P2pCommand>>,
     pub p2p_running: Arc<AtomicBool>,
     pub p2p_thread_handle: Option<std::thread::JoinHandle<()>>,
+    pub local_peer_id: Option<String>,
+    pub last_transfer: Option<TransferEvent>,
+    pub outbox: VecDeque<P2pCommand>, // Commands that must not be dropped when the channel is full
+    pub peers: Vec<PeerInfo>,
+    pub network_error: Option<String>,
+    pub identity: Identity,
+    pub identity_path: String, // Where the identity is exported to and imported from
+
+    // Manual connections
+    pub invite: Option<String>, // Ours, once the network listens
+    pub show_invite: bool,
+    pub connect_draft: String,
+    pub connect_error: Option<String>,
+    pub known_peers: Vec<KnownPeer>,
+    pub presences: HashMap<String, (Presence, f64)>, // Peer ID -> presence, last received
+    pub last_presence: Option<(Presence, f64)>,      // Last sent, and when
+    pub show_viewports: bool,
+    pub presenting: bool,
+    pub following: Option<String>, // Peer ID whose camera and selection are mirrored
+
+    // Sync rooms
+    pub room: Option<Room>,
+    pub discovered_rooms: HashMap<String, (Room, f64)>, // Join code -> room, last announced
+    pub room_name_draft: String,
+    pub join_code_draft: String,
+    pub passphrase_draft: String,
+    pub room_passphrase: Option<String>, // Never saved, has to be entered again after a restart
+    pub room_problem: Option<OpenError>,
+    pub room_notice: Option<String>, // Why the room was left, e.g. kicked by the host
+    pub roster: Roster,
+    pub join_requests: HashMap<String, (String, f64)>, // Peer ID -> name, last asked
+    pub roles_sent_at: f64,                            // Last roster or join request sent
+    pub catch_up: Option<CatchUp>,
+    pub catch_up_replies: HashMap<u64, f64>, // Request -> when to answer it
 
     // Panel
     pub show_menu_panel: bool,
     pub menu_p2p_enabled: bool,
+    pub board_path: String,
+
+    // Frame export
+    pub frame_export_dialog: Option<FrameExportDialog>,
+    pub frame_exports: Vec<FrameExportJob>,
+
+    // Animation playback, keyed by image
+    pub playbacks: HashMap<ItemId, Playback>,
+
+    // Tools and ink
+    pub tool: CanvasTool,
+    pub ink_color: [u8; 4],
+    pub ink_width: f32,
+    pub ink_attach_to_images: bool,
+    pub strokes: Vec<InkStroke>,
+    pub active_stroke: Option<InkStroke>,
+    pub ink_undo: Vec<InkEdit>,
+    pub ink_redo: Vec<InkEdit>,
+    pub shapes: Vec<ShapeItem>,
+    pub active_shape: Option<ShapeItem>,
+
+    // Orientation and colour corrected textures, keyed by image
+    pub display_images: HashMap<ItemId, DisplayImage>,
+
+    // Composition overlays and source image sizes, keyed by image
+    pub overlays: HashMap<ItemId, ImageOverlays>,
+    pub image_sizes: HashMap<ItemId, [u32; 2]>,
+    pub ruler_image: Option<ItemId>,
+
+    // Eyedropper
+    pub source_pixels: HashMap<ItemId, SourcePixels>,
+    pub picked_colors: Vec<[u8; 3]>,
+
+    // Palettes
+    pub swatches: Vec<SwatchItem>,
+    pub palette_size: usize,
+    pub palette_jobs: Vec<PaletteJob>,
+
+    // Comments
+    pub comment_pins: Vec<CommentPin>,
+    pub pending_pin: Option<CommentPin>, // Placed, but not sent until it has a first comment
+    pub open_comment: Option<ItemId>,
+    pub comment_draft: String,
+
+    // Image inspector
+    pub selected_image: Option<ItemId>,
+    pub exif: HashMap<ItemId, Option<ExifSummary>>,
+    pub source_paths: HashMap<ItemId, String>, // Of images imported here, never synced
+
+    // Search
+    pub search: Search,
+    pub tag_draft: String,
+    pub label_draft: Option<(ItemId, ItemLabels)>, // Image tags and description being edited
+
+    // Canvas space rects of the items from the last frame
+    pub image_rects: HashMap<ItemId, egui::Rect>,
+    pub text_rects: HashMap<u64, egui::Rect>,
+    pub swatch_rects: HashMap<ItemId, egui::Rect>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NetworkStatus {
+    Off,
+    Searching, // Running, but not connected to anyone
+    Connected,
+    Error,
+}
+
+impl NetworkStatus {
+    fn color(self) -> egui::Color32 {
+        match self {
+            NetworkStatus::Off => egui::Color32::GRAY,
+            NetworkStatus::Searching => egui::Color32::YELLOW,
+            NetworkStatus::Connected => egui::Color32::GREEN,
+            NetworkStatus::Error => egui::Color32::RED,
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CanvasTool {
+    #[default]
+    Select,
+    Pen,
+    Highlighter,
+    Eraser,
+    Shape(ShapeKind),
+    Comment,
+    Eyedropper,
+    Ruler,
+}
+
+pub struct FrameExportDialog {
+    pub image: ItemId,
+    pub format: ExportFormat,
+    pub all_frames: bool,
+    pub in_frame: usize,
+    pub out_frame: usize,
+    pub output_dir: String,
+}
+
+/// How a still image is shown. The original bytes are kept for export and sync either way.
+pub enum DisplayImage {
+    Decoding(std::sync::mpsc::Receiver<std::result::Result<Option<image::RgbaImage>, String>>),
+    Original,
+    Corrected(egui::TextureHandle),
+}
+
+pub struct PaletteJob {
+    pub image: ItemId,
+    pub receiver: std::sync::mpsc::Receiver<std::result::Result<Vec<[u8; 3]>, String>>,
+}
+
+pub struct FrameExportJob {
+    pub name: String,
+    pub receiver: std::sync::mpsc::Receiver<ExportEvent>,
+    pub progress: (usize, usize),
+    pub result: Option<std::result::Result<PathBuf, String>>,
+}
+
+/// Fetching the board from the room after joining it or reconnecting.
+pub struct CatchUp {
+    pub request: u64,
+    pub asked_at: f64,
+    pub attempts: u32,
+    pub chunks: Option<(usize, usize)>, // Snapshot chunks received, and expected
+    pub snapshot_at: Option<f64>,
 }
 
 impl App {
     pub fn new() -> Self {
+        let identity = Identity::load_or_create();
+        let local_peer_id = identity.peer_id();
         Self {
             transform: TSTransform::default(),
             images: vec![],
+            board_path: "board.muse".to_owned(),
+            ink_color: [230, 50, 50, 255],
+            ink_width: 4.0,
+            ink_attach_to_images: true,
+            palette_size: 6,
+            actor: rand::random(),
+            identity,
+            identity_path: "identity.muse-id".to_owned(),
+            known_peers: load_known_peers(),
+            local_peer_id: Some(local_peer_id),
             ..Default::default()
         }
     }
@@ -59,6 +264,10 @@ impl App {
 
         if response.dragged() {
             self.transform.translation += response.drag_delta();
+            self.following = None;
+        }
+        if response.clicked() {
+            self.selected_image = None;
         }
 
         if let Some(pointer) = ui.ctx().input(|i| i.pointer.hover_pos()) {
@@ -66,6 +275,9 @@ impl App {
                 let pointer_in_layer = self.transform.inverse() * pointer;
                 let zoom_delta = ui.ctx().input(|i| i.zoom_delta());
                 let pan_delta = ui.ctx().input(|i| i.smooth_scroll_delta);
+                if zoom_delta != 1.0 || pan_delta != egui::Vec2::ZERO {
+                    self.following = None;
+                }
 
                 // Zoom in on pointer:
                 self.transform = self.transform
@@ -85,217 +297,3807 @@ impl App {
         rect: egui::Rect,
         parent_window: egui::LayerId,
         widget: impl Widget,
-        count: usize,
-    ) {
-        use egui::Id;
-        let id = egui::Area::new(Id::new("floating_image").with(count))
+        id: egui::Id,
+        position: Option<egui::Pos2>,
+    ) -> egui::Response {
+        let mut area = egui::Area::new(id)
             // .default_pos() // TODO: figure out position later. Also WINIT does not send pointer move events when draging files.
             .order(egui::Order::Middle)
-            .constrain(false)
-            .show(ui.ctx(), |ui| {
-                ui.set_clip_rect(self.transform.inverse() * rect);
+            .constrain(false);
 
-                ui.add(widget);
-            })
-            .response
-            .layer_id;
+        // Items that keep their own canvas position are moved by the app, not by egui.
+        if let Some(position) = position {
+            area = area.fixed_pos(position);
+        }
+
+        let area = area.show(ui.ctx(), |ui| {
+            ui.set_clip_rect(self.transform.inverse() * rect);
+
+            ui.add(widget)
+        });
+        let id = area.response.layer_id;
 
         ui.ctx().set_transform_layer(id, self.transform);
         ui.ctx().set_sublayer(parent_window, id);
+
+        area.inner
     }
 
-    pub fn ui_file_drag_and_drop(&mut self, ctx: &egui::Context) {
-        // TODO:
-        // WINIT does not support dragging non files. Like images/text from things like browsers into the window.
-        use egui::{Color32, Id, LayerId, Order, TextStyle};
-        use std::fmt::Write as _;
+    pub fn add_text_item(&mut self, ctx: &egui::Context) {
+        let position = self.transform.inverse() * ctx.screen_rect().center();
+        let item = TextItem::new([position.x, position.y]);
+        self.editing_text = Some(item.id);
+        self.texts.push(item);
+    }
 
-        if !ctx.input(|i| i.raw.hovered_files.is_empty()) {
-            let text = ctx.input(|i| {
-                let mut text = "Dropping files:\n".to_owned();
-                for file in &i.raw.hovered_files {
-                    if let Some(path) = &file.path {
-                        write!(text, "\n{}", path.display()).ok();
+    pub fn ui_text_items(&mut self, ui: &egui::Ui, rect: egui::Rect, parent_window: egui::LayerId) {
+        let mut texts = std::mem::take(&mut self.texts);
+        let mut deleted = None;
+
+        for text in texts.iter_mut() {
+            let hit = SearchHit::Text(text.id);
+            if self.filtered_out(hit) {
+                self.text_rects.remove(&text.id);
+                continue;
+            }
+
+            let editing = self.editing_text == Some(text.id);
+            let position = egui::pos2(text.position[0], text.position[1]);
+            let id = egui::Id::new("floating_text").with(text.id);
+            let widget = search_dimmed(canvas_text(text, editing), self.search.is_match(hit));
+
+            let response =
+                self.add_floating_widget(ui, rect, parent_window, widget, id, Some(position));
+            self.text_rects.insert(text.id, response.rect);
+
+            if response.dragged() {
+                let delta = response.drag_delta();
+                text.position[0] += delta.x;
+                text.position[1] += delta.y;
+            }
+            if response.double_clicked() {
+                self.editing_text = Some(text.id);
+            }
+            let clicked_elsewhere = ui.input(|i| i.pointer.any_pressed()) && !response.hovered();
+            if editing && (response.lost_focus() || clicked_elsewhere) {
+                self.editing_text = None;
+            }
+
+            response.context_menu(|ui| {
+                if ui.button("Edit").clicked() {
+                    self.editing_text = Some(text.id);
+                    ui.close_menu();
+                }
+                ui.add(egui::Slider::new(&mut text.font_size, 6.0..=96.0).text("Font size"));
+                ui.add(
+                    egui::Slider::new(&mut text.scale, 0.1..=10.0)
+                        .logarithmic(true)
+                        .text("Scale"),
+                );
+
+                ui.horizontal(|ui| {
+                    ui.label("Colour");
+                    color_edit_rgba(ui, &mut text.color);
+                });
+
+                ui.horizontal(|ui| {
+                    let mut has_background = text.background.is_some();
+                    if ui.checkbox(&mut has_background, "Background").changed() {
+                        text.background = has_background.then_some([40, 40, 40, 220]);
+                    }
+                    if let Some(background) = &mut text.background {
+                        color_edit_rgba(ui, background);
                     }
+                });
+
+                ui.menu_button("Tags and description", |ui| {
+                    ui_labels_editor(ui, &mut text.labels, &mut self.tag_draft);
+                });
+
+                ui.separator();
+                if ui.button("Delete").clicked() {
+                    deleted = Some(text.id);
+                    ui.close_menu();
                 }
-                text
             });
+        }
 
-            // Instead of this, we will paint image preview
-            // once again, this is not possible now due to winit limitation
-            let painter =
-                ctx.layer_painter(LayerId::new(Order::Foreground, Id::new("file_drop_target")));
-            let screen_rect = ctx.screen_rect();
-            painter.rect_filled(screen_rect, 0.0, Color32::from_black_alpha(192));
-            painter.text(
-                screen_rect.center(),
-                egui::Align2::CENTER_CENTER,
-                text,
-                TextStyle::Heading.resolve(&ctx.style()),
-                Color32::WHITE,
-            );
+        if let Some(id) = deleted {
+            texts.retain(|text| text.id != id);
+            self.text_rects.remove(&id);
         }
+        self.texts = texts;
+    }
 
-        if let Some(receiver) = &self.file_loader_channel {
-            if let anyhow::Result::Ok(bytes) = receiver.try_recv() {
-                self.dropped_bytes.push(bytes);
-                self.file_loader_channel = None;
+    pub fn ui_annotation_layer(
+        &mut self,
+        ctx: &egui::Context,
+        rect: egui::Rect,
+        canvas_layer: egui::LayerId,
+    ) {
+        // Pointer input over the whole canvas is only captured while a drawing tool is active.
+        // Any widget in a layer on top would otherwise block the items below it.
+        if self.tool == CanvasTool::Select {
+            self.ui_shape_handles(ctx, rect, canvas_layer);
+            self.ui_comment_pins(ctx, rect, canvas_layer);
+        } else {
+            let input = egui::Area::new(egui::Id::new("ink_input"))
+                .order(egui::Order::Middle)
+                .fixed_pos(rect.min)
+                .show(ctx, |ui| {
+                    let response = ui.allocate_response(rect.size(), egui::Sense::click_and_drag());
+                    match self.tool {
+                        CanvasTool::Shape(kind) => self.handle_shape_input(&response, kind),
+                        CanvasTool::Comment => self.handle_comment_input(&response),
+                        CanvasTool::Eyedropper => self.handle_eyedropper_input(ctx, &response),
+                        CanvasTool::Ruler => self.handle_ruler_input(&response),
+                        _ => self.handle_ink_input(&response),
+                    }
+                });
+            raise_overlay(ctx, canvas_layer, input.response.layer_id);
+        }
+
+        // Shapes and ink are painted above every item, in canvas coordinates
+        let ink_layer = egui::LayerId::new(egui::Order::Middle, egui::Id::new("ink_layer"));
+        ctx.set_transform_layer(ink_layer, self.transform);
+        raise_overlay(ctx, canvas_layer, ink_layer);
+        let painter = ctx
+            .layer_painter(ink_layer)
+            .with_clip_rect(self.transform.inverse() * rect);
+
+        let targets = AnchorTargets {
+            images: &self.image_rects,
+            texts: &self.text_rects,
+        };
+        for shape in self.shapes.iter().chain(&self.active_shape) {
+            if let Some((start, end)) = resolve_endpoints(shape, &targets) {
+                paint_shape(&painter, shape, start, end);
             }
         }
 
-        // TODO: improve this code.
-        // read_file_bytes does not cover failing case.
-        // If a file is not image, weird things might happen, since non images are also not handled.
-        ctx.input(|i| {
-            for file in &i.raw.dropped_files {
-                if let Some(path) = &file.path {
-                    let (sender, receiver) = std::sync::mpsc::channel();
-                    self.file_loader_channel = Some(receiver);
+        let mut strokes: Vec<&InkStroke> = self.strokes.iter().chain(&self.active_stroke).collect();
+        strokes.sort_by_key(|stroke| !stroke.highlighter);
 
-                    let path_clone = path.clone();
-                    thread::spawn(move || {
-                        if let anyhow::Result::Ok(bytes) = read_file_bytes(&path_clone) {
-                            sender.send(bytes).unwrap();
-                        } else {
-                            // TODO: failing cases.
-                        }
-                    });
-                }
+        for stroke in strokes {
+            if let Some(points) = canvas_points(stroke, &self.image_rects) {
+                paint_stroke(&painter, points, stroke);
             }
-        })
+        }
+
+        // Pins keep their size at any zoom
+        let pin_layer = egui::LayerId::new(egui::Order::Middle, egui::Id::new("pin_layer"));
+        raise_overlay(ctx, canvas_layer, pin_layer);
+        let painter = ctx.layer_painter(pin_layer).with_clip_rect(rect);
+        for pin in self.comment_pins.iter().chain(&self.pending_pin) {
+            if let Some(position) = self.pin_position(pin) {
+                paint_comment_pin(&painter, self.transform * position, pin);
+            }
+        }
     }
 
-    pub fn send_state(&self) {
-        if let Some(sender) = &self.gui_sender {
-            let _a = sender
-                .try_send(MessageType::CanvasState {
-                    state: SyncableState::from(self),
-                })
-                .map_err(|err| println!("{:?}", err));
+    /// Canvas position of a comment pin, resolved from its image relative anchor.
+    fn pin_position(&self, pin: &CommentPin) -> Option<egui::Pos2> {
+        let rect = self.image_rects.get(&pin.image)?;
+        Some(rect.min + rect.size() * egui::vec2(pin.anchor[0], pin.anchor[1]))
+    }
+
+    fn pin_at(&self, pointer: egui::Pos2) -> Option<ItemId> {
+        self.comment_pins
+            .iter()
+            .filter_map(|pin| Some((pin.id, self.transform * self.pin_position(pin)?)))
+            .find(|(_, center)| center.distance(pointer) <= PIN_RADIUS)
+            .map(|(id, _)| id)
+    }
+
+    fn handle_comment_input(&mut self, response: &egui::Response) {
+        if !response.clicked() {
+            return;
+        }
+        let Some(pointer) = response.interact_pointer_pos() else {
+            return;
+        };
+
+        if let Some(pin) = self.pin_at(pointer) {
+            self.open_comment = Some(pin);
+            return;
+        }
+
+        let position = self.transform.inverse() * pointer;
+        if let Some(image) = self.image_at(position) {
+            let rect = self.image_rects[&image];
+            let anchor = (position - rect.min) / rect.size();
+            let pin = CommentPin {
+                id: new_item_id(),
+                image,
+                anchor: [anchor.x, anchor.y],
+                resolved: false,
+                comments: vec![],
+            };
+            self.open_comment = Some(pin.id);
+            self.pending_pin = Some(pin);
+            self.comment_draft.clear();
         }
     }
 
-    pub fn handle_p2p_messages(&mut self) {
-        if let Some(ref mut p2p) = self.p2p_receiver {
-            if let anyhow::Result::Ok(message) = p2p.try_recv() {
-                match message {
-                    MessageType::NewImage { bytes } => {
-                        self.dropped_bytes.push(bytes);
-                    }
-                    MessageType::CanvasState { state } => {
-                        self.dropped_bytes = state.dropped_bytes;
+    /// Clickable comment pins while the select tool is active.
+    fn ui_comment_pins(
+        &mut self,
+        ctx: &egui::Context,
+        rect: egui::Rect,
+        canvas_layer: egui::LayerId,
+    ) {
+        let pins = egui::Area::new(egui::Id::new("comment_pins"))
+            .order(egui::Order::Middle)
+            .fixed_pos(rect.min)
+            .show(ctx, |ui| {
+                ui.set_clip_rect(rect);
+                for pin in &self.comment_pins {
+                    let Some(position) = self.pin_position(pin) else {
+                        continue;
+                    };
+                    let pin_rect = egui::Rect::from_center_size(
+                        self.transform * position,
+                        egui::Vec2::splat(PIN_RADIUS * 2.0),
+                    );
+                    let response = ui
+                        .interact(
+                            pin_rect,
+                            egui::Id::new("pin").with(pin.id),
+                            egui::Sense::click(),
+                        )
+                        .on_hover_cursor(egui::CursorIcon::PointingHand);
+                    if response.clicked() {
+                        self.open_comment = Some(pin.id);
+                        self.comment_draft.clear();
                     }
                 }
-            }
-        }
+            });
+        raise_overlay(ctx, canvas_layer, pins.response.layer_id);
     }
 
-    pub fn start_network_sync(&mut self) {
-        if self.p2p_running.load(Ordering::Relaxed) {
+    fn local_author(&self) -> String {
+        self.local_peer_id
+            .clone()
+            .unwrap_or_else(|| "local".to_owned())
+    }
+
+    pub fn ui_comment_thread(&mut self, ctx: &egui::Context) {
+        let Some(open) = self.open_comment else {
             return;
+        };
+        let pin = self
+            .comment_pins
+            .iter()
+            .chain(&self.pending_pin)
+            .find(|pin| pin.id == open)
+            .cloned();
+        let Some(pin) = pin else {
+            self.open_comment = None;
+            return;
+        };
+
+        let position = self
+            .pin_position(&pin)
+            .map(|position| self.transform * position + egui::vec2(PIN_RADIUS * 2.0, 0.0));
+        let mut window_open = true;
+        let mut operations = vec![];
+        let mut reply = false;
+
+        let mut window = egui::Window::new("Comments")
+            .id(egui::Id::new("comment_thread"))
+            .open(&mut window_open)
+            .collapsible(false)
+            .resizable(false)
+            .default_width(260.0);
+        if let Some(position) = position {
+            window = window.current_pos(position);
         }
 
-        self.p2p_running.store(true, Ordering::Relaxed);
+        window.show(ctx, |ui| {
+            let now = crate::playback::unix_time_ms();
+            egui::ScrollArea::vertical()
+                .max_height(240.0)
+                .show(ui, |ui| {
+                    for comment in &pin.comments {
+                        ui.horizontal(|ui| {
+                            ui.strong(short_peer_id(&comment.author));
+                            ui.weak(format_age(now, comment.timestamp_ms));
+                        });
+                        ui.label(&comment.text);
+                        ui.separator();
+                    }
+                });
 
-        let (gui_sender, gui_receiver) = mpsc::channel::<MessageType>(1);
-        let (p2p_sender, p2p_receiver) = mpsc::channel::<MessageType>(1);
-        let p2p_running = Arc::clone(&self.p2p_running);
+            ui.add(
+                egui::TextEdit::multiline(&mut self.comment_draft)
+                    .hint_text(if pin.comments.is_empty() {
+                        "Comment"
+                    } else {
+                        "Reply"
+                    })
+                    .desired_rows(2),
+            );
 
-        self.p2p_receiver = Some(p2p_receiver);
-        self.gui_sender = Some(gui_sender);
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(
+                        !self.comment_draft.trim().is_empty(),
+                        egui::Button::new("Send"),
+                    )
+                    .clicked()
+                {
+                    reply = true;
+                }
 
-        let handle = std::thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(p2p::p2p(gui_receiver, p2p_sender, p2p_running));
+                if !pin.comments.is_empty() {
+                    let label = if pin.resolved { "Reopen" } else { "Resolve" };
+                    if ui.button(label).clicked() {
+                        operations.push(OperationKind::Resolve {
+                            resolved: !pin.resolved,
+                        });
+                    }
+                    if ui.button("Delete").clicked() {
+                        operations.push(OperationKind::DeletePin);
+                    }
+                }
+            });
         });
 
-        self.p2p_thread_handle = Some(handle);
+        if reply {
+            let comment = Comment {
+                id: new_item_id(),
+                author: self.local_author(),
+                timestamp_ms: crate::playback::unix_time_ms(),
+                text: std::mem::take(&mut self.comment_draft).trim().to_owned(),
+            };
+            match self.pending_pin.take() {
+                Some(pending) if pending.id == pin.id => {
+                    operations.push(OperationKind::AddPin {
+                        image: pending.image,
+                        anchor: pending.anchor,
+                    });
+                }
+                pending => self.pending_pin = pending,
+            }
+            operations.push(OperationKind::Reply { comment });
+        }
+
+        if !operations.is_empty() {
+            for kind in operations {
+                self.board_operation(pin.id, kind);
+            }
+            self.refresh_items();
+        }
+
+        if !window_open {
+            self.open_comment = None;
+            self.pending_pin = None;
+            self.comment_draft.clear();
+        }
     }
 
-    pub fn stop_network_sync(&mut self) {
-        if !self.p2p_running.load(Ordering::Relaxed) {
-            return;
+    fn handle_shape_input(&mut self, response: &egui::Response, kind: ShapeKind) {
+        let position = response
+            .interact_pointer_pos()
+            .map(|pointer| self.transform.inverse() * pointer);
+
+        if response.drag_started() {
+            if let Some(position) = position {
+                self.active_shape = Some(ShapeItem {
+                    id: new_item_id(),
+                    kind,
+                    start: self.shape_anchor_at(position, kind),
+                    end: ShapeAnchor::Point([position.x, position.y]),
+                    width: self.ink_width,
+                    color: self.ink_color,
+                });
+            }
         }
 
-        self.p2p_running.store(false, Ordering::Relaxed);
+        if let (Some(shape), Some(position)) = (&mut self.active_shape, position) {
+            shape.end = ShapeAnchor::Point([position.x, position.y]);
+        }
 
-        self.p2p_receiver = None;
-        self.gui_sender = None;
+        if response.drag_stopped() {
+            if let Some(mut shape) = self.active_shape.take() {
+                if let ShapeAnchor::Point([x, y]) = shape.end {
+                    shape.end = self.shape_anchor_at(egui::pos2(x, y), kind);
+                }
+                if shape.start != shape.end {
+                    self.shapes.push(shape);
+                }
+            }
+        }
     }
-}
 
-impl eframe::App for App {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        if self.menu_p2p_enabled {
-            self.start_network_sync();
-        } else {
-            self.stop_network_sync();
+    /// Lines and arrows attach to the item under the end point. Other shapes stay where they are drawn.
+    fn shape_anchor_at(&self, position: egui::Pos2, kind: ShapeKind) -> ShapeAnchor {
+        if matches!(kind, ShapeKind::Arrow | ShapeKind::Line) {
+            if let Some((id, _)) = self
+                .text_rects
+                .iter()
+                .find(|(_, rect)| rect.contains(position))
+            {
+                return ShapeAnchor::Text(*id);
+            }
+            if let Some(image) = self.image_at(position) {
+                return ShapeAnchor::Image(image);
+            }
         }
 
-        TopBottomPanel::top("top_panel").show(ctx, |ui| {
-            ui.horizontal(|ui| {
-                if ui.button("Menu").clicked() {
-                    self.show_menu_panel = !self.show_menu_panel;
-                }
-            })
-        });
+        ShapeAnchor::Point([position.x, position.y])
+    }
 
-        if self.show_menu_panel {
-            SidePanel::left("menu_panel").show(ctx, |ui| {
-                ui.heading("Menu");
-                ui.separator();
+    /// Samples the source pixels of the image under the pointer, not the rendered screen.
+    fn handle_eyedropper_input(&mut self, ctx: &egui::Context, response: &egui::Response) {
+        let Some(pointer) = response.hover_pos() else {
+            return;
+        };
+        let position = self.transform.inverse() * pointer;
+        let Some(image_id) = self.image_at(position) else {
+            return;
+        };
+        let Some(bytes) = self.image_bytes(image_id) else {
+            return;
+        };
 
-                Grid::new("menu_grid").show(ui, |ui| {
-                    ui.label("P2P server");
-                    ui.add(toggle(&mut self.menu_p2p_enabled));
-                    ui.end_row();
+        let frames = self
+            .source_pixels
+            .entry(image_id)
+            .or_insert_with(|| SourcePixels::decode(bytes.to_vec()))
+            .poll();
+        let Some(frames) = frames else {
+            ctx.request_repaint();
+            return;
+        };
 
-                    // TODO: P2P status here
-                    // ui.label("P2P status");
-                    // ui.painter().circle(
-                        // (0.0, 0.0).into(),
-                        // 20.0,
-                        // if self.p2p_running.load(Ordering::Relaxed) {
-                            // egui::Color32::GREEN
-                        // } else {
-                            // egui::Color32::RED
-                        // },
-                        // egui::Stroke::new(1.0, egui::Color32::BLACK),
-                    // );
-                    // ui.end_row();
+        // Animated images are sampled on the frame that is currently shown.
+        let frame = if frames.len() > 1 {
+            self.animation_frame(ctx, &image_uri(image_id), image_id)
+        } else {
+            0
+        };
+        let Some(image) = frames.get(frame) else {
+            return;
+        };
+        let Some((x, y)) = source_pixel(image, self.image_rects[&image_id], position) else {
+            return;
+        };
 
-                    ui.label("Manual state sync");
-                    if ui.add(egui::Button::new("Send state")).clicked() {
-                        self.send_state();
-                    }
-                    ui.end_row();
-                })
-            });
+        let painter = ctx.layer_painter(egui::LayerId::new(
+            egui::Order::Tooltip,
+            egui::Id::new("eyedropper_loupe"),
+        ));
+        paint_loupe(&painter, pointer, image, (x, y));
+        ctx.set_cursor_icon(egui::CursorIcon::Crosshair);
+
+        if response.clicked() {
+            let [r, g, b, _] = image.get_pixel(x, y).0;
+            self.pick_color(ctx, [r, g, b]);
         }
+    }
 
-        // CANVAS
-        egui::CentralPanel::default().show(ctx, |ui| {
-            let rect = ui.min_rect();
-            let window_layer = ui.layer_id();
-            self.manage_canvas_movement(ui);
+    /// Dragging over an image measures between two points, in source image pixels.
+    fn handle_ruler_input(&mut self, response: &egui::Response) {
+        if response.drag_stopped() {
+            self.ruler_image = None;
+            return;
+        }
+        let Some(pointer) = response.interact_pointer_pos() else {
+            return;
+        };
+        let position = self.transform.inverse() * pointer;
 
-            for image in self.images.iter_mut() {
-                let i = canvas_image(image);
-                ui.add(i);
-            }
+        if response.drag_started() {
+            self.ruler_image = self.image_at(position);
+        }
+        let Some(image_id) = self.ruler_image else {
+            return;
+        };
+        let (Some(rect), Some([width, height])) = (
+            self.image_rects.get(&image_id).copied(),
+            self.source_size(image_id),
+        ) else {
+            return;
+        };
 
-            for (count, bytes) in self.dropped_bytes.iter().enumerate() {
-                let uri = format!("bytes://image_{}", count);
-                let e_bytes = egui::load::Bytes::from(bytes.clone());
-                let widget = egui::Image::from_bytes(uri, e_bytes);
+        let uv =
+            ((position - rect.min) / rect.size()).clamp(egui::Vec2::ZERO, egui::Vec2::splat(1.0));
+        let point = [uv.x * width as f32, uv.y * height as f32];
+        let overlays = self.overlays.entry(image_id).or_default();
+        overlays.ruler = match (response.drag_started(), overlays.ruler) {
+            (false, Some([start, _])) => Some([start, point]),
+            _ => Some([point, point]),
+        };
+    }
 
-                self.add_floating_widget(ui, rect, window_layer, widget, count);
-            }
+    /// Pixel size of the source image, as displayed. Only the header is read.
+    fn source_size(&mut self, image_id: ItemId) -> Option<[u32; 2]> {
+        if let Some(DisplayImage::Corrected(texture)) = self.display_images.get(&image_id) {
+            let [width, height] = texture.size();
+            return Some([width as u32, height as u32]);
+        }
+        if let Some(size) = self.image_sizes.get(&image_id) {
+            return Some(*size);
+        }
+
+        let bytes = self.image_bytes(image_id)?;
+        let (width, height) = image::ImageReader::new(std::io::Cursor::new(&bytes[..]))
+            .with_guessed_format()
+            .ok()?
+            .into_dimensions()
+            .ok()?;
+        self.image_sizes.insert(image_id, [width, height]);
+        Some([width, height])
+    }
+
+    fn ui_overlay_menu(&mut self, ui: &mut egui::Ui, image_id: ItemId) {
+        let overlays = self.overlays.entry(image_id).or_default();
+        ui.checkbox(&mut overlays.thirds, "Rule of thirds");
+        ui.checkbox(&mut overlays.golden_ratio, "Golden ratio");
+        ui.checkbox(&mut overlays.center_lines, "Centre lines");
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut overlays.grid, "Grid");
+            ui.add(egui::DragValue::new(&mut overlays.grid_size[0]).range(1..=64));
+            ui.label("×");
+            ui.add(egui::DragValue::new(&mut overlays.grid_size[1]).range(1..=64));
         });
+        if ui
+            .add_enabled(overlays.ruler.is_some(), egui::Button::new("Clear ruler"))
+            .clicked()
+        {
+            overlays.ruler = None;
+        }
+    }
 
-        self.ui_file_drag_and_drop(ctx);
-        self.handle_p2p_messages();
+    fn pick_color(&mut self, ctx: &egui::Context, rgb: [u8; 3]) {
+        self.picked_colors.retain(|picked| *picked != rgb);
+        self.picked_colors.insert(0, rgb);
+        self.picked_colors.truncate(HISTORY_LENGTH);
+
+        let [r, g, b] = rgb;
+        self.ink_color = [r, g, b, 255];
+        ctx.copy_text(color_descriptions(rgb)[0].clone());
     }
+
+    fn ui_picked_colors(&mut self, ui: &mut egui::Ui) {
+        let mut picked = None;
+        for rgb in &self.picked_colors {
+            let [r, g, b] = *rgb;
+            let button = egui::Button::new("")
+                .fill(egui::Color32::from_rgb(r, g, b))
+                .min_size(egui::Vec2::splat(16.0));
+            if ui
+                .add(button)
+                .on_hover_text(color_descriptions(*rgb).join("\n"))
+                .clicked()
+            {
+                picked = Some(*rgb);
+            }
+        }
+
+        if let Some(rgb) = picked {
+            self.pick_color(ui.ctx(), rgb);
+        }
+    }
+
+    /// Small handles to move, re-attach and edit shapes while the select tool is active.
+    fn ui_shape_handles(
+        &mut self,
+        ctx: &egui::Context,
+        rect: egui::Rect,
+        canvas_layer: egui::LayerId,
+    ) {
+        const HANDLE_RADIUS: f32 = 5.0;
+
+        let handles = egui::Area::new(egui::Id::new("shape_handles"))
+            .order(egui::Order::Middle)
+            .fixed_pos(rect.min)
+            .show(ctx, |ui| {
+                ui.set_clip_rect(rect);
+                let mut shapes = std::mem::take(&mut self.shapes);
+                let mut deleted = None;
+
+                for shape in shapes.iter_mut() {
+                    let targets = AnchorTargets {
+                        images: &self.image_rects,
+                        texts: &self.text_rects,
+                    };
+                    let Some((start, end)) = resolve_endpoints(shape, &targets) else {
+                        continue;
+                    };
+
+                    let id = egui::Id::new("shape").with(shape.id);
+                    let handles = [
+                        (id.with("start"), start),
+                        (id.with("end"), end),
+                        (id.with("body"), start.lerp(end, 0.5)),
+                    ];
+
+                    for (handle_id, position) in handles {
+                        let center = self.transform * position;
+                        let handle_rect = egui::Rect::from_center_size(
+                            center,
+                            egui::Vec2::splat(HANDLE_RADIUS * 3.0),
+                        );
+                        let response =
+                            ui.interact(handle_rect, handle_id, egui::Sense::click_and_drag());
+
+                        let fill = if response.hovered() || response.dragged() {
+                            egui::Color32::WHITE
+                        } else {
+                            egui::Color32::from_white_alpha(60)
+                        };
+                        ui.painter().circle(
+                            center,
+                            HANDLE_RADIUS,
+                            fill,
+                            egui::Stroke::new(1.0, egui::Color32::BLACK),
+                        );
+
+                        let delta = response.drag_delta() / self.transform.scaling;
+                        let pointer = response
+                            .interact_pointer_pos()
+                            .map(|pointer| self.transform.inverse() * pointer);
+
+                        if handle_id == id.with("body") {
+                            for anchor in [&mut shape.start, &mut shape.end] {
+                                if let ShapeAnchor::Point([x, y]) = anchor {
+                                    *x += delta.x;
+                                    *y += delta.y;
+                                }
+                            }
+
+                            response.context_menu(|ui| {
+                                ui_shape_menu(ui, shape);
+                                ui.separator();
+                                if ui.button("Delete").clicked() {
+                                    deleted = Some(shape.id);
+                                    ui.close_menu();
+                                }
+                            });
+                            continue;
+                        }
+
+                        let anchor = if handle_id == id.with("start") {
+                            &mut shape.start
+                        } else {
+                            &mut shape.end
+                        };
+                        if response.dragged() {
+                            if let Some(pointer) = pointer {
+                                *anchor = ShapeAnchor::Point([pointer.x, pointer.y]);
+                            }
+                        }
+                        if response.drag_stopped() {
+                            if let ShapeAnchor::Point([x, y]) = *anchor {
+                                *anchor = self.shape_anchor_at(egui::pos2(x, y), shape.kind);
+                            }
+                        }
+                    }
+                }
+
+                if let Some(id) = deleted {
+                    shapes.retain(|shape| shape.id != id);
+                }
+                self.shapes = shapes;
+            });
+        raise_overlay(ctx, canvas_layer, handles.response.layer_id);
+    }
+
+    fn handle_ink_input(&mut self, response: &egui::Response) {
+        if response.drag_stopped() {
+            if let Some(stroke) = self.active_stroke.take() {
+                self.strokes.push(stroke.clone());
+                self.push_ink_edit(InkEdit::Draw(stroke));
+            }
+            return;
+        }
+
+        let Some(pointer) = response.interact_pointer_pos() else {
+            return;
+        };
+        let position = self.transform.inverse() * pointer;
+
+        match self.tool {
+            CanvasTool::Pen | CanvasTool::Highlighter => {
+                if response.drag_started() {
+                    let highlighter = self.tool == CanvasTool::Highlighter;
+                    let [r, g, b, a] = self.ink_color;
+                    self.active_stroke = Some(InkStroke {
+                        id: new_item_id(),
+                        points: vec![],
+                        width: if highlighter {
+                            self.ink_width * 4.0
+                        } else {
+                            self.ink_width
+                        },
+                        color: if highlighter {
+                            [r, g, b, 96]
+                        } else {
+                            [r, g, b, a]
+                        },
+                        highlighter,
+                        attached_to: self
+                            .ink_attach_to_images
+                            .then(|| self.image_at(position))
+                            .flatten(),
+                    });
+                }
+
+                if let Some(stroke) = &mut self.active_stroke {
+                    let origin = stroke
+                        .attached_to
+                        .and_then(|image| self.image_rects.get(&image))
+                        .map(|rect| rect.min.to_vec2())
+                        .unwrap_or_default();
+                    let point = position - origin;
+                    stroke.points.push([point.x, point.y]);
+                }
+            }
+            CanvasTool::Eraser => {
+                let radius = self.ink_width.max(8.0) / self.transform.scaling;
+                let (erased, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.strokes)
+                    .into_iter()
+                    .partition(|stroke| {
+                        canvas_points(stroke, &self.image_rects)
+                            .is_some_and(|points| hits(&points, position, radius))
+                    });
+
+                self.strokes = kept;
+                if !erased.is_empty() {
+                    self.push_ink_edit(InkEdit::Erase(erased));
+                }
+            }
+            CanvasTool::Select
+            | CanvasTool::Shape(_)
+            | CanvasTool::Comment
+            | CanvasTool::Eyedropper
+            | CanvasTool::Ruler => {}
+        }
+    }
+
+    /// Topmost image under the canvas position.
+    fn image_at(&self, position: egui::Pos2) -> Option<ItemId> {
+        self.board
+            .ordered()
+            .into_iter()
+            .rev()
+            .map(|(id, _)| id)
+            .find(|id| {
+                self.image_rects
+                    .get(id)
+                    .is_some_and(|rect| rect.contains(position))
+            })
+    }
+
+    /// Original bytes of an image on the board. `None` until they have been fetched from a peer.
+    fn image_bytes(&self, image_id: ItemId) -> Option<Arc<[u8]>> {
+        let image = self.board.image(image_id)?;
+        self.blobs.get(&image.info.value.content_hash)
+    }
+
+    fn push_ink_edit(&mut self, edit: InkEdit) {
+        self.ink_undo.push(edit);
+        self.ink_redo.clear();
+    }
+
+    pub fn undo_ink(&mut self) {
+        if let Some(edit) = self.ink_undo.pop() {
+            self.revert_ink_edit(&edit);
+            self.ink_redo.push(edit);
+        }
+    }
+
+    pub fn redo_ink(&mut self) {
+        if let Some(edit) = self.ink_redo.pop() {
+            self.apply_ink_edit(&edit);
+            self.ink_undo.push(edit);
+        }
+    }
+
+    fn apply_ink_edit(&mut self, edit: &InkEdit) {
+        match edit {
+            InkEdit::Draw(stroke) => self.strokes.push(stroke.clone()),
+            InkEdit::Erase(erased) => self
+                .strokes
+                .retain(|stroke| !erased.iter().any(|e| e.id == stroke.id)),
+        }
+    }
+
+    fn revert_ink_edit(&mut self, edit: &InkEdit) {
+        match edit {
+            InkEdit::Draw(drawn) => self.strokes.retain(|stroke| stroke.id != drawn.id),
+            InkEdit::Erase(erased) => self.strokes.extend(erased.iter().cloned()),
+        }
+    }
+
+    pub fn ui_tool_bar(&mut self, ui: &mut egui::Ui) {
+        ui.separator();
+        for (tool, label) in [
+            (CanvasTool::Select, "Select"),
+            (CanvasTool::Pen, "Pen"),
+            (CanvasTool::Highlighter, "Highlighter"),
+            (CanvasTool::Eraser, "Eraser"),
+            (CanvasTool::Shape(ShapeKind::Arrow), "Arrow"),
+            (CanvasTool::Shape(ShapeKind::Line), "Line"),
+            (CanvasTool::Shape(ShapeKind::Rectangle), "Rectangle"),
+            (CanvasTool::Shape(ShapeKind::Ellipse), "Ellipse"),
+            (CanvasTool::Comment, "Comment"),
+            (CanvasTool::Eyedropper, "Eyedropper"),
+            (CanvasTool::Ruler, "Ruler"),
+        ] {
+            ui.selectable_value(&mut self.tool, tool, label);
+        }
+
+        if self.tool == CanvasTool::Eyedropper {
+            self.ui_picked_colors(ui);
+        } else if !matches!(
+            self.tool,
+            CanvasTool::Select | CanvasTool::Comment | CanvasTool::Ruler
+        ) {
+            color_edit_rgba(ui, &mut self.ink_color);
+            ui.add(egui::Slider::new(&mut self.ink_width, 1.0..=32.0).text("Width"));
+            if !matches!(self.tool, CanvasTool::Shape(_)) {
+                ui.checkbox(&mut self.ink_attach_to_images, "Attach to images");
+            }
+        }
+
+        if ui
+            .add_enabled(!self.ink_undo.is_empty(), egui::Button::new("Undo"))
+            .clicked()
+        {
+            self.undo_ink();
+        }
+        if ui
+            .add_enabled(!self.ink_redo.is_empty(), egui::Button::new("Redo"))
+            .clicked()
+        {
+            self.redo_ink();
+        }
+    }
+
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        use egui::{Key, KeyboardShortcut, Modifiers};
+
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+
+        let redo = KeyboardShortcut::new(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z);
+        let undo = KeyboardShortcut::new(Modifiers::COMMAND, Key::Z);
+        if ctx.input_mut(|i| i.consume_shortcut(&redo)) {
+            self.redo_ink();
+        } else if ctx.input_mut(|i| i.consume_shortcut(&undo)) {
+            self.undo_ink();
+        }
+    }
+
+    pub fn ui_swatch_items(
+        &mut self,
+        ui: &egui::Ui,
+        rect: egui::Rect,
+        parent_window: egui::LayerId,
+    ) {
+        let mut deleted = None;
+
+        for index in 0..self.swatches.len() {
+            let swatch = &self.swatches[index];
+            let hit = SearchHit::Swatch(swatch.id);
+            if self.filtered_out(hit) {
+                self.swatch_rects.remove(&swatch.id);
+                continue;
+            }
+
+            let position = egui::pos2(swatch.position[0], swatch.position[1]);
+            let id = egui::Id::new("floating_swatch").with(swatch.id);
+            let widget = search_dimmed(canvas_swatch(swatch), self.search.is_match(hit));
+            let response =
+                self.add_floating_widget(ui, rect, parent_window, widget, id, Some(position));
+            self.swatch_rects.insert(swatch.id, response.rect);
+
+            let swatch = &mut self.swatches[index];
+            if response.dragged() {
+                let delta = response.drag_delta();
+                swatch.position[0] += delta.x;
+                swatch.position[1] += delta.y;
+            }
+
+            let hovered = response
+                .hover_pos()
+                .and_then(|pos| swatch_index_at(swatch, response.rect, pos));
+            if let Some(hovered) = hovered {
+                let rgb = swatch.colors[hovered];
+                if response.clicked() {
+                    ui.ctx().copy_text(color_descriptions(rgb)[0].clone());
+                }
+                let response = response.clone();
+                response.on_hover_text(color_descriptions(rgb).join("\n"));
+            }
+
+            response.context_menu(|ui| {
+                for rgb in &swatch.colors {
+                    ui.horizontal(|ui| {
+                        let [r, g, b] = *rgb;
+                        let (color_rect, _) =
+                            ui.allocate_exact_size(egui::Vec2::splat(16.0), egui::Sense::hover());
+                        ui.painter()
+                            .rect_filled(color_rect, 2.0, egui::Color32::from_rgb(r, g, b));
+
+                        for description in color_descriptions(*rgb) {
+                            if ui.button(&description).on_hover_text("Copy").clicked() {
+                                ui.ctx().copy_text(description);
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                }
+                ui.menu_button("Tags and description", |ui| {
+                    ui_labels_editor(ui, &mut swatch.labels, &mut self.tag_draft);
+                });
+                ui.separator();
+                if ui.button("Delete").clicked() {
+                    deleted = Some(swatch.id);
+                    ui.close_menu();
+                }
+            });
+        }
+
+        if let Some(id) = deleted {
+            self.swatches.retain(|swatch| swatch.id != id);
+        }
+    }
+
+    pub fn extract_palette(&mut self, image_id: ItemId) {
+        if let Some(bytes) = self.image_bytes(image_id) {
+            self.palette_jobs.push(PaletteJob {
+                image: image_id,
+                receiver: spawn_extract_palette(bytes.to_vec(), self.palette_size),
+            });
+        }
+    }
+
+    fn handle_palette_jobs(&mut self) {
+        let mut finished = vec![];
+        for (index, job) in self.palette_jobs.iter().enumerate() {
+            match job.receiver.try_recv() {
+                std::result::Result::Ok(result) => finished.push((index, job.image, result)),
+                Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                    finished.push((index, job.image, Err("Extraction failed".to_owned())))
+                }
+                Err(std::sync::mpsc::TryRecvError::Empty) => {}
+            }
+        }
+
+        for (index, image_id, result) in finished.into_iter().rev() {
+            self.palette_jobs.remove(index);
+            match result {
+                std::result::Result::Ok(colors) => {
+                    // Place the swatch right next to the image
+                    let position = self
+                        .image_rects
+                        .get(&image_id)
+                        .map(|rect| rect.right_top() + egui::vec2(16.0, 0.0))
+                        .unwrap_or_default();
+                    self.swatches.push(SwatchItem {
+                        id: new_item_id(),
+                        position: [position.x, position.y],
+                        colors,
+                        labels: ItemLabels::default(),
+                    });
+                }
+                Err(err) => println!("Failed to extract palette: {err}"),
+            }
+        }
+    }
+
+    pub fn save_board(&self) {
+        let file = BoardFile {
+            state: SyncableState::from(self),
+            blobs: self.blobs.subset(
+                self.board
+                    .ordered()
+                    .into_iter()
+                    .map(|(_, image)| image.info.value.content_hash.as_str()),
+            ),
+            room: self.room.clone(),
+            roster: self.roster.clone(),
+            source_paths: self.source_paths.clone(),
+        };
+        if let Err(err) = save_board(&PathBuf::from(&self.board_path), &file) {
+            println!("Failed to save board: {err:?}");
+        }
+    }
+
+    pub fn load_board(&mut self) {
+        match load_board(&PathBuf::from(&self.board_path)) {
+            anyhow::Result::Ok(file) => {
+                for (hash, bytes) in file.blobs {
+                    if !self.blobs.insert_verified(&hash, bytes) {
+                        println!("Image {hash} in the board file is corrupt");
+                    }
+                }
+                // Opening a file replaces the board, unlike state received from peers
+                self.board = Board::default();
+                self.apply_state(file.state);
+                self.source_paths = file.source_paths;
+                match file.room {
+                    Some(room) => {
+                        let hosting = self.is_local_peer(&room.host);
+                        self.join_room(room, None);
+                        // Approvals given before are kept
+                        if hosting {
+                            self.roster = file.roster;
+                            self.share_roster();
+                        }
+                    }
+                    None => self.leave_room(),
+                }
+            }
+            Err(err) => println!("Failed to load board: {err:?}"),
+        }
+    }
+
+    /// Merges a board received from a peer, or read from a file.
+    pub fn apply_state(&mut self, state: SyncableState) {
+        self.board.merge(&state.board);
+        self.refresh_items();
+        self.forget_removed_images();
+    }
+
+    /// Rebuilds the texts, strokes, shapes, swatches and comment pins from the board.
+    fn refresh_items(&mut self) {
+        self.texts.clear();
+        self.strokes.clear();
+        self.shapes.clear();
+        self.swatches.clear();
+        for (_, item) in self.board.items() {
+            match item.clone() {
+                Item::Text(text) => self.texts.push(text),
+                Item::Stroke(stroke) => self.strokes.push(stroke),
+                Item::Shape(shape) => self.shapes.push(shape),
+                Item::Swatch(swatch) => self.swatches.push(swatch),
+            }
+        }
+        self.comment_pins = self
+            .board
+            .pins()
+            .into_iter()
+            .map(|(id, pin)| CommentPin::from_board(id, pin))
+            .collect();
+
+        let board = &self.board;
+        self.text_rects.retain(|id, _| board.item(*id).is_some());
+        self.swatch_rects.retain(|id, _| board.item(*id).is_some());
+    }
+
+    /// Turns edits of texts, strokes, shapes and swatches into operations. Like image moves,
+    /// changes made while the pointer is down are only applied here, and peers get the final value.
+    fn commit_item_edits(&mut self, ctx: &egui::Context) {
+        // Compared in place, only changed items are cloned
+        let board = &self.board;
+        let mut edits: Vec<(ItemId, Item)> = vec![];
+        for text in &self.texts {
+            if !matches!(board.item(text.id), Some(Item::Text(synced)) if synced == text) {
+                edits.push((text.id, Item::Text(text.clone())));
+            }
+        }
+        for stroke in &self.strokes {
+            if !matches!(board.item(stroke.id), Some(Item::Stroke(synced)) if synced == stroke) {
+                edits.push((stroke.id, Item::Stroke(stroke.clone())));
+            }
+        }
+        for shape in &self.shapes {
+            if !matches!(board.item(shape.id), Some(Item::Shape(synced)) if synced == shape) {
+                edits.push((shape.id, Item::Shape(shape.clone())));
+            }
+        }
+        for swatch in &self.swatches {
+            if !matches!(board.item(swatch.id), Some(Item::Swatch(synced)) if synced == swatch) {
+                edits.push((swatch.id, Item::Swatch(swatch.clone())));
+            }
+        }
+
+        let local: HashSet<ItemId> = self
+            .texts
+            .iter()
+            .map(|text| text.id)
+            .chain(self.strokes.iter().map(|stroke| stroke.id))
+            .chain(self.shapes.iter().map(|shape| shape.id))
+            .chain(self.swatches.iter().map(|swatch| swatch.id))
+            .collect();
+        let removed: Vec<ItemId> = board
+            .items
+            .iter()
+            .filter(|(id, item)| item.value.is_some() && !local.contains(id))
+            .map(|(id, _)| *id)
+            .collect();
+
+        if !self.can_edit() {
+            // Viewers' edits are undone
+            if !edits.is_empty() || !removed.is_empty() {
+                self.refresh_items();
+            }
+            return;
+        }
+
+        // Typing into a text, description or tag field is sent once it loses focus
+        let settled = !ctx.input(|i| i.pointer.any_down()) && !ctx.wants_keyboard_input();
+        if settled {
+            // Edits made during a drag or while typing are sent now
+            for id in std::mem::take(&mut self.unsent_items) {
+                if let Some(item) = self.board.item(id) {
+                    if !edits.iter().any(|(edited, _)| *edited == id) {
+                        edits.push((id, item.clone()));
+                    }
+                }
+            }
+        }
+        for (id, item) in edits {
+            let kind = OperationKind::Put { item };
+            if settled {
+                self.board_operation(id, kind);
+            } else {
+                self.board.local(self.actor, id, kind);
+                self.unsent_items.insert(id);
+            }
+        }
+        for id in removed {
+            self.unsent_items.remove(&id);
+            self.board_operation(id, OperationKind::Remove);
+        }
+    }
+
+    /// Drops the per image caches of images that are no longer on the board.
+    fn forget_removed_images(&mut self) {
+        let board = &self.board;
+        self.image_rects.retain(|id, _| board.image(*id).is_some());
+        self.source_pixels
+            .retain(|id, _| board.image(*id).is_some());
+        self.overlays.retain(|id, _| board.image(*id).is_some());
+        self.image_sizes.retain(|id, _| board.image(*id).is_some());
+        self.display_images
+            .retain(|id, _| board.image(*id).is_some());
+        self.exif.retain(|id, _| board.image(*id).is_some());
+        self.playbacks.retain(|id, _| board.image(*id).is_some());
+        self.source_paths.retain(|id, _| board.image(*id).is_some());
+        if self
+            .selected_image
+            .is_some_and(|id| board.image(id).is_none())
+        {
+            self.selected_image = None;
+        }
+    }
+
+    /// Applies a board operation locally and queues it for the other peers.
+    pub fn board_operation(&mut self, item: ItemId, kind: OperationKind) {
+        if !self.can_edit() {
+            return;
+        }
+        let operation = self.board.local(self.actor, item, kind);
+        if operation.kind == OperationKind::Delete {
+            self.forget_removed_images();
+        }
+        self.outbox
+            .push_back(P2pCommand::Broadcast(MessageType::Operation { operation }));
+    }
+
+    /// Sends queued messages, as many as the channel takes.
+    fn flush_outbox(&mut self) {
+        let Some(sender) = &self.gui_sender else {
+            // Peers catch up on the whole state when they connect
+            self.outbox.clear();
+            return;
+        };
+
+        while let Some(message) = self.outbox.pop_front() {
+            match sender.try_send(message) {
+                std::result::Result::Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(message)) => {
+                    self.outbox.push_front(message);
+                    break;
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => {
+                    self.outbox.clear();
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Side panel with the metadata of the selected image.
+    pub fn ui_image_inspector(&mut self, ctx: &egui::Context) {
+        let Some(image_id) = self.selected_image else {
+            return;
+        };
+        let Some(image) = self.board.image(image_id) else {
+            self.selected_image = None;
+            return;
+        };
+        let Some(bytes) = self.blobs.get(&image.info.value.content_hash) else {
+            return;
+        };
+
+        let mut info = image.info.value.clone();
+        let size = self.source_size(image_id);
+        let format = image_format(&bytes);
+        let file_size = bytes.len();
+        let exif = self
+            .exif
+            .entry(image_id)
+            .or_insert_with(|| read_exif(&bytes))
+            .clone();
+        let mut open = true;
+        let mut changed = false;
+
+        SidePanel::right("image_inspector").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.heading("Image");
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    if ui.button("Close").clicked() {
+                        open = false;
+                    }
+                });
+            });
+            ui.separator();
+
+            Grid::new("image_inspector_grid")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    let unknown = || "unknown".to_owned();
+
+                    ui.label("Dimensions");
+                    ui.label(size.map_or_else(unknown, |[w, h]| format!("{w} × {h} px")));
+                    ui.end_row();
+
+                    ui.label("Format");
+                    ui.label(format.unwrap_or_else(unknown));
+                    ui.end_row();
+
+                    ui.label("File size");
+                    ui.label(format_file_size(file_size));
+                    ui.end_row();
+
+                    ui.label("File name");
+                    ui.label(info.file_name.clone().unwrap_or_else(unknown));
+                    ui.end_row();
+
+                    // Only known where the image was imported
+                    if let Some(path) = self.source_paths.get(&image_id) {
+                        ui.label("Original path");
+                        ui.label(path);
+                        ui.end_row();
+                    }
+
+                    ui.label("Imported");
+                    ui.label(match info.imported_at_ms {
+                        0 => unknown(),
+                        imported_at_ms => format_utc(imported_at_ms),
+                    });
+                    ui.end_row();
+
+                    ui.label("SHA-256");
+                    let short_hash = &info.content_hash[..info.content_hash.len().min(16)];
+                    if ui
+                        .button(format!("{short_hash}…"))
+                        .on_hover_text("Copy")
+                        .clicked()
+                    {
+                        ui.ctx().copy_text(info.content_hash.clone());
+                    }
+                    ui.end_row();
+                });
+
+            ui.separator();
+            ui.strong("EXIF");
+            match exif {
+                Some(exif) => {
+                    Grid::new("image_inspector_exif")
+                        .num_columns(2)
+                        .show(ui, |ui| {
+                            let fields = [
+                                ("Camera", exif.camera),
+                                ("Lens", exif.lens),
+                                ("Date", exif.date),
+                                (
+                                    "Orientation",
+                                    exif.orientation.map(|orientation| {
+                                        orientation_name(orientation).to_owned()
+                                    }),
+                                ),
+                            ];
+                            for (label, value) in fields {
+                                ui.label(label);
+                                ui.label(value.unwrap_or_else(|| "-".to_owned()));
+                                ui.end_row();
+                            }
+                        });
+                }
+                None => {
+                    ui.weak("No EXIF data");
+                }
+            }
+
+            ui.separator();
+            ui.strong("Source");
+            changed |= ui
+                .add(
+                    egui::TextEdit::singleline(&mut info.source_url)
+                        .hint_text("Source or credit URL"),
+                )
+                .changed();
+            if !info.source_url.trim().is_empty() {
+                ui.hyperlink_to("Open source", info.source_url.trim());
+            }
+        });
+
+        if changed {
+            self.board_operation(image_id, OperationKind::Describe { info });
+        }
+        if !open {
+            self.selected_image = None;
+        }
+    }
+
+    /// Adds an image on top of the others, with its top left corner at `position`.
+    pub fn add_image(
+        &mut self,
+        bytes: Vec<u8>,
+        mut info: ImageInfo,
+        position: egui::Pos2,
+    ) -> ItemId {
+        // Peers only get the hash, and fetch the bytes when they need them
+        info.content_hash = self.blobs.insert(bytes);
+        let z = self.board.top_z();
+        let id = new_item_id();
+        self.board_operation(
+            id,
+            OperationKind::Add {
+                info,
+                position: [position.x, position.y],
+                z,
+            },
+        );
+        id
+    }
+
+    /// Recomputes the search results. Hundreds of items are cheap enough to check every frame.
+    pub fn update_search(&mut self) {
+        if !self.search.is_active() {
+            self.search.hits.clear();
+            return;
+        }
+
+        let query = Query::parse(&self.search.query);
+        let mut hits = vec![];
+
+        for (id, image) in self.board.ordered() {
+            let info = &image.info.value;
+            let name = info.file_name.as_deref();
+            let path = self.source_paths.get(&id).map(String::as_str);
+            let format = self
+                .blobs
+                .get(&info.content_hash)
+                .as_deref()
+                .and_then(image_format);
+            let searchable = Searchable {
+                labels: &info.labels,
+                name,
+                path,
+                format: format.as_deref(),
+                text: None,
+            };
+            if searchable.matches(&query) {
+                hits.push(SearchHit::Image(id));
+            }
+        }
+
+        for text in &self.texts {
+            let searchable = Searchable {
+                labels: &text.labels,
+                name: None,
+                path: None,
+                format: None,
+                text: Some(&text.text),
+            };
+            if searchable.matches(&query) {
+                hits.push(SearchHit::Text(text.id));
+            }
+        }
+
+        for swatch in &self.swatches {
+            let searchable = Searchable {
+                labels: &swatch.labels,
+                name: None,
+                path: None,
+                format: None,
+                text: None,
+            };
+            if searchable.matches(&query) {
+                hits.push(SearchHit::Swatch(swatch.id));
+            }
+        }
+
+        self.search.hits = hits;
+    }
+
+    /// Whether an item is hidden by the search filter.
+    fn filtered_out(&self, hit: SearchHit) -> bool {
+        self.search.mode == SearchMode::Filter && !self.search.is_match(hit)
+    }
+
+    fn hit_rect(&self, hit: SearchHit) -> Option<egui::Rect> {
+        match hit {
+            SearchHit::Image(id) => self.image_rects.get(&id),
+            SearchHit::Text(id) => self.text_rects.get(&id),
+            SearchHit::Swatch(id) => self.swatch_rects.get(&id),
+        }
+        .copied()
+    }
+
+    /// Fits `target`, in canvas space, into the visible canvas.
+    pub fn zoom_to_rect(&mut self, viewport: egui::Rect, target: egui::Rect) {
+        let target = target.expand2(target.size() * 0.1);
+        let scaling = (viewport.width() / target.width())
+            .min(viewport.height() / target.height())
+            .clamp(0.05, 20.0);
+        let translation = viewport.center().to_vec2() - target.center().to_vec2() * scaling;
+        self.transform = TSTransform::new(translation, scaling);
+        // Moving the camera on purpose ends following
+        self.following = None;
+    }
+
+    pub fn ui_search_bar(&mut self, ui: &mut egui::Ui) {
+        ui.separator();
+
+        let response = ui
+            .add(
+                egui::TextEdit::singleline(&mut self.search.query)
+                    .hint_text("Search")
+                    .desired_width(200.0),
+            )
+            .on_hover_text(
+                "Matches tags, descriptions, file names, paths and formats.\n\
+                 Narrow a term down with tag:, name:, path: or format:",
+            );
+        if response.changed() {
+            self.search.current = None;
+        }
+        let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+
+        ui.selectable_value(&mut self.search.mode, SearchMode::Highlight, "Highlight");
+        ui.selectable_value(&mut self.search.mode, SearchMode::Filter, "Filter");
+
+        if self.search.is_active() {
+            let found = self.search.hits.len();
+            match self.search.current {
+                Some(current) if current < found => ui.label(format!("{}/{found}", current + 1)),
+                _ => ui.label(format!("{found} found")),
+            };
+        }
+
+        let zoom = ui
+            .add_enabled(
+                !self.search.hits.is_empty(),
+                egui::Button::new("Zoom to result"),
+            )
+            .clicked();
+        if zoom || submitted {
+            self.search.zoom_to_next();
+        }
+    }
+
+    /// Outlines the search results while searching in highlight mode.
+    pub fn paint_search_highlights(&self, ctx: &egui::Context, rect: egui::Rect) {
+        if !self.search.is_active() || self.search.mode != SearchMode::Highlight {
+            return;
+        }
+
+        let painter = ctx
+            .layer_painter(egui::LayerId::new(
+                egui::Order::Foreground,
+                egui::Id::new("search_highlights"),
+            ))
+            .with_clip_rect(rect);
+        let current = self
+            .search
+            .current
+            .and_then(|current| self.search.hits.get(current));
+
+        for hit in &self.search.hits {
+            let Some(item_rect) = self.hit_rect(*hit) else {
+                continue;
+            };
+            let width = if current == Some(hit) { 4.0 } else { 2.0 };
+            painter.rect_stroke(
+                (self.transform * item_rect).expand(3.0),
+                4.0,
+                egui::Stroke::new(width, egui::Color32::from_rgb(255, 210, 0)),
+            );
+        }
+    }
+
+    /// Decodes still images with EXIF orientation and ICC profile applied.
+    /// Images that need neither are left to the egui loaders.
+    pub fn display_image(&mut self, ctx: &egui::Context, image_id: ItemId) -> &DisplayImage {
+        let bytes = self.image_bytes(image_id);
+        let display = self
+            .display_images
+            .entry(image_id)
+            .or_insert_with(|| match bytes {
+                Some(bytes) => DisplayImage::Decoding(spawn_decode_corrected(bytes.to_vec())),
+                None => DisplayImage::Original,
+            });
+
+        if let DisplayImage::Decoding(receiver) = display {
+            match receiver.try_recv() {
+                std::result::Result::Ok(std::result::Result::Ok(Some(image))) => {
+                    let size = [image.width() as usize, image.height() as usize];
+                    let pixels = egui::ColorImage::from_rgba_unmultiplied(size, &image);
+                    let texture = ctx.load_texture(
+                        format!("display_image_{image_id}"),
+                        pixels,
+                        Default::default(),
+                    );
+                    *display = DisplayImage::Corrected(texture);
+                    // Measured before the corrections were known
+                    self.image_sizes.remove(&image_id);
+                }
+                std::result::Result::Ok(_) | Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                    *display = DisplayImage::Original;
+                }
+                Err(std::sync::mpsc::TryRecvError::Empty) => {
+                    ctx.request_repaint_after(std::time::Duration::from_millis(50));
+                }
+            }
+        }
+
+        display
+    }
+
+    /// Returns the frame of an animated image that should be shown according to its playback state.
+    pub fn animation_frame(&mut self, ctx: &egui::Context, uri: &str, image_id: ItemId) -> usize {
+        let Some(durations) = animation_durations(ctx, uri) else {
+            return 0;
+        };
+
+        let now = ctx.input(|i| i.time);
+        let playback = self.playbacks.entry(image_id).or_default();
+        let (frame, until_next_frame) = frame_at(&durations, playback.position_ms(now));
+        if playback.playing && until_next_frame != std::time::Duration::MAX {
+            ctx.request_repaint_after(until_next_frame);
+        }
+
+        frame
+    }
+
+    pub fn playback_event(&mut self, ctx: &egui::Context, image_id: ItemId, event: PlaybackEvent) {
+        let now = ctx.input(|i| i.time);
+        let stamp = self.board.tick(self.actor);
+        let update = self
+            .playbacks
+            .entry(image_id)
+            .or_default()
+            .apply_local(event, now, image_id, stamp);
+
+        // Viewers can play animations for themselves
+        if !self.can_edit() {
+            return;
+        }
+        self.outbox
+            .push_back(P2pCommand::Broadcast(MessageType::Playback { update }));
+    }
+
+    fn ui_playback_controls(&mut self, ui: &mut egui::Ui, image_id: ItemId) {
+        // Only GIFs are stepped frame by frame, animated PNG and WebP show their first frame
+        let Some(durations) = animation_durations(ui.ctx(), &image_uri(image_id))
+            .filter(|durations| durations.len() > 1)
+        else {
+            if self.is_animated(ui.ctx(), image_id) {
+                ui.weak("Playback is only available for GIFs");
+            }
+            return;
+        };
+
+        let now = ui.ctx().input(|i| i.time);
+        let playback = self.playbacks.entry(image_id).or_default();
+        let (mut frame, _) = frame_at(&durations, playback.position_ms(now));
+        let playing = playback.playing;
+        let mut rate = playback.rate;
+        let mut event = None;
+
+        ui.horizontal(|ui| {
+            if ui.button("⏮").clicked() {
+                let previous = frame.checked_sub(1).unwrap_or(durations.len() - 1);
+                event = Some(PlaybackEvent::Seek {
+                    position_ms: frame_start_ms(&durations, previous),
+                });
+            }
+            if ui.button(if playing { "⏸" } else { "▶" }).clicked() {
+                event = Some(if playing {
+                    PlaybackEvent::Pause
+                } else {
+                    PlaybackEvent::Play
+                });
+            }
+            if ui.button("⏭").clicked() {
+                let next = (frame + 1) % durations.len();
+                event = Some(PlaybackEvent::Seek {
+                    position_ms: frame_start_ms(&durations, next),
+                });
+            }
+        });
+
+        let slider = ui.add(
+            egui::Slider::new(&mut frame, 0..=durations.len() - 1)
+                .text(format!("frame / {}", durations.len())),
+        );
+        if slider.changed() {
+            event = Some(PlaybackEvent::Seek {
+                position_ms: frame_start_ms(&durations, frame),
+            });
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Speed");
+            for preset in [0.25, 0.5, 1.0, 2.0] {
+                if ui
+                    .selectable_value(&mut rate, preset, format!("{preset}x"))
+                    .clicked()
+                {
+                    event = Some(PlaybackEvent::Rate { rate });
+                }
+            }
+        });
+
+        if let Some(event) = event {
+            self.playback_event(ui.ctx(), image_id, event);
+        }
+    }
+
+    /// Whether the image has more than one frame.
+    fn is_animated(&self, ctx: &egui::Context, image_id: ItemId) -> bool {
+        if let Some(durations) = animation_durations(ctx, &image_uri(image_id)) {
+            return durations.len() > 1;
+        }
+        self.board
+            .image(image_id)
+            .and_then(|image| self.blobs.get(&image.info.value.content_hash))
+            .is_some_and(|bytes| has_animation(&bytes))
+    }
+
+    pub fn image_context_menu(&mut self, response: &egui::Response, image_id: ItemId) {
+        let menu = response.context_menu(|ui| {
+            self.ui_playback_controls(ui, image_id);
+
+            ui.menu_button("Overlays", |ui| self.ui_overlay_menu(ui, image_id));
+            ui.menu_button("Tags and description", |ui| {
+                let Some(image) = self.board.image(image_id) else {
+                    return;
+                };
+                let labels = match &mut self.label_draft {
+                    Some((id, labels)) if *id == image_id => labels,
+                    draft => &mut draft.insert((image_id, image.info.value.labels.clone())).1,
+                };
+                if ui_labels_editor(ui, labels, &mut self.tag_draft) {
+                    self.commit_label_draft(image_id);
+                }
+            });
+            ui.menu_button("Arrange", |ui| {
+                for (step, label) in [
+                    (Reorder::ToFront, "Bring to front"),
+                    (Reorder::Forward, "Bring forward"),
+                    (Reorder::Backward, "Send backward"),
+                    (Reorder::ToBack, "Send to back"),
+                ] {
+                    let z = self.board.reorder_z(image_id, step);
+                    if ui
+                        .add_enabled(z.is_some(), egui::Button::new(label))
+                        .clicked()
+                    {
+                        if let Some(z) = z {
+                            self.board_operation(image_id, OperationKind::Reorder { z });
+                        }
+                        ui.close_menu();
+                    }
+                }
+            });
+            self.ui_image_scale(ui, image_id);
+
+            ui.horizontal(|ui| {
+                if ui.button("Extract palette").clicked() {
+                    self.extract_palette(image_id);
+                    ui.close_menu();
+                }
+                ui.add(egui::DragValue::new(&mut self.palette_size).range(1..=16))
+                    .on_hover_text("Number of colours");
+            });
+
+            if self.is_animated(ui.ctx(), image_id) && ui.button("Export frames...").clicked() {
+                self.frame_export_dialog = Some(FrameExportDialog {
+                    image: image_id,
+                    format: ExportFormat::PngSequence,
+                    all_frames: true,
+                    in_frame: 0,
+                    out_frame: 0,
+                    output_dir: "muse_export".to_owned(),
+                });
+                ui.close_menu();
+            }
+
+            ui.separator();
+            if ui.button("Delete").clicked() {
+                self.board_operation(image_id, OperationKind::Delete);
+                ui.close_menu();
+            }
+        });
+        // Closing the menu does not report the description losing focus
+        if menu.is_none() {
+            self.commit_label_draft(image_id);
+        }
+    }
+
+    /// Sends the tags and description edited in an image's menu, if they changed.
+    fn commit_label_draft(&mut self, image_id: ItemId) {
+        if self.label_draft.as_ref().map(|(id, _)| *id) != Some(image_id) {
+            return;
+        }
+        let Some((_, labels)) = self.label_draft.take() else {
+            return;
+        };
+        let Some(image) = self.board.image(image_id) else {
+            return;
+        };
+        if labels != image.info.value.labels {
+            let mut info = image.info.value.clone();
+            info.labels = labels;
+            self.board_operation(image_id, OperationKind::Describe { info });
+        }
+    }
+
+    /// Scale slider. Changes are shown right away, but only sent once the slider is let go.
+    fn ui_image_scale(&mut self, ui: &mut egui::Ui, image_id: ItemId) {
+        let Some(image) = self.board.image(image_id) else {
+            return;
+        };
+        let mut transform = image.transform.value;
+
+        let response = ui.add(
+            egui::Slider::new(&mut transform.scale, 0.05..=20.0)
+                .logarithmic(true)
+                .text("Scale"),
+        );
+        if response.changed() || response.drag_stopped() {
+            let kind = OperationKind::Transform { transform };
+            if !self.can_edit() {
+                return;
+            }
+            if response.dragged() {
+                self.board.local(self.actor, image_id, kind);
+            } else {
+                self.board_operation(image_id, kind);
+            }
+        }
+    }
+
+    pub fn ui_frame_export(&mut self, ctx: &egui::Context) {
+        let mut start_export = false;
+        let mut open = self.frame_export_dialog.is_some();
+
+        if let Some(dialog) = &mut self.frame_export_dialog {
+            egui::Window::new("Export frames")
+                .open(&mut open)
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    Grid::new("frame_export_grid").show(ui, |ui| {
+                        ui.label("Format");
+                        ui.horizontal(|ui| {
+                            ui.radio_value(
+                                &mut dialog.format,
+                                ExportFormat::PngSequence,
+                                "PNG sequence",
+                            );
+                            ui.radio_value(
+                                &mut dialog.format,
+                                ExportFormat::SpriteSheet,
+                                "Sprite sheet",
+                            );
+                        });
+                        ui.end_row();
+
+                        ui.label("All frames");
+                        ui.checkbox(&mut dialog.all_frames, "");
+                        ui.end_row();
+
+                        ui.label("In / out");
+                        ui.add_enabled_ui(!dialog.all_frames, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.add(egui::DragValue::new(&mut dialog.in_frame));
+                                ui.add(egui::DragValue::new(&mut dialog.out_frame));
+                            });
+                        });
+                        ui.end_row();
+
+                        ui.label("Output folder");
+                        ui.text_edit_singleline(&mut dialog.output_dir);
+                        ui.end_row();
+                    });
+
+                    if ui.button("Export").clicked() {
+                        start_export = true;
+                    }
+                });
+        }
+
+        if start_export {
+            if let Some(dialog) = self.frame_export_dialog.take() {
+                if let Some(bytes) = self.image_bytes(dialog.image) {
+                    let name = format!("image_{:016x}", dialog.image);
+                    let receiver = spawn_export(ExportRequest {
+                        bytes: bytes.to_vec(),
+                        format: dialog.format,
+                        range: (!dialog.all_frames).then_some(dialog.in_frame..=dialog.out_frame),
+                        output_dir: PathBuf::from(dialog.output_dir),
+                        name: name.clone(),
+                    });
+                    self.frame_exports.push(FrameExportJob {
+                        name,
+                        receiver,
+                        progress: (0, 0),
+                        result: None,
+                    });
+                }
+            }
+        } else if !open {
+            self.frame_export_dialog = None;
+        }
+
+        self.ui_frame_export_progress(ctx);
+    }
+
+    fn ui_frame_export_progress(&mut self, ctx: &egui::Context) {
+        for job in self.frame_exports.iter_mut() {
+            while let std::result::Result::Ok(event) = job.receiver.try_recv() {
+                match event {
+                    ExportEvent::Progress { done, total } => job.progress = (done, total),
+                    ExportEvent::Finished { path } => {
+                        job.result = Some(std::result::Result::Ok(path))
+                    }
+                    ExportEvent::Failed { error } => job.result = Some(Err(error)),
+                }
+            }
+        }
+
+        if self.frame_exports.is_empty() {
+            return;
+        }
+
+        let mut dismissed = vec![];
+        egui::Window::new("Exports")
+            .anchor(egui::Align2::RIGHT_BOTTOM, [-10.0, -10.0])
+            .collapsible(true)
+            .resizable(false)
+            .show(ctx, |ui| {
+                for (index, job) in self.frame_exports.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(&job.name);
+                        match &job.result {
+                            None => {
+                                let (done, total) = job.progress;
+                                let fraction = if total == 0 {
+                                    0.0
+                                } else {
+                                    done as f32 / total as f32
+                                };
+                                ui.add(
+                                    egui::ProgressBar::new(fraction)
+                                        .desired_width(150.0)
+                                        .text(format!("{done}/{total}")),
+                                );
+                                ctx.request_repaint();
+                            }
+                            Some(result) => {
+                                match result {
+                                    std::result::Result::Ok(path) => {
+                                        ui.label(format!("Saved to {}", path.display()))
+                                    }
+                                    Err(error) => ui.colored_label(egui::Color32::RED, error),
+                                };
+                                if ui.small_button("x").clicked() {
+                                    dismissed.push(index);
+                                }
+                            }
+                        }
+                    });
+                }
+            });
+
+        for index in dismissed.into_iter().rev() {
+            self.frame_exports.remove(index);
+        }
+    }
+
+    pub fn ui_file_drag_and_drop(&mut self, ctx: &egui::Context) {
+        // TODO:
+        // WINIT does not support dragging non files. Like images/text from things like browsers into the window.
+        use egui::{Color32, Id, LayerId, Order, TextStyle};
+        use std::fmt::Write as _;
+
+        if !ctx.input(|i| i.raw.hovered_files.is_empty()) {
+            let text = ctx.input(|i| {
+                let mut text = "Dropping files:\n".to_owned();
+                for file in &i.raw.hovered_files {
+                    if let Some(path) = &file.path {
+                        write!(text, "\n{}", path.display()).ok();
+                    }
+                }
+                text
+            });
+
+            // Instead of this, we will paint image preview
+            // once again, this is not possible now due to winit limitation
+            let painter =
+                ctx.layer_painter(LayerId::new(Order::Foreground, Id::new("file_drop_target")));
+            let screen_rect = ctx.screen_rect();
+            painter.rect_filled(screen_rect, 0.0, Color32::from_black_alpha(192));
+            painter.text(
+                screen_rect.center(),
+                egui::Align2::CENTER_CENTER,
+                text,
+                TextStyle::Heading.resolve(&ctx.style()),
+                Color32::WHITE,
+            );
+        }
+
+        if let Some(receiver) = &self.file_loader_channel {
+            if let anyhow::Result::Ok((bytes, path)) = receiver.try_recv() {
+                let info = ImageInfo {
+                    file_name: path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned()),
+                    imported_at_ms: crate::playback::unix_time_ms(),
+                    ..Default::default()
+                };
+                let position = self.transform.inverse() * ctx.screen_rect().center();
+                let id = self.add_image(bytes, info, position);
+                self.source_paths.insert(id, path.display().to_string());
+                self.file_loader_channel = None;
+            }
+        }
+
+        // TODO: improve this code.
+        // read_file_bytes does not cover failing case.
+        // If a file is not image, weird things might happen, since non images are also not handled.
+        ctx.input(|i| {
+            for file in &i.raw.dropped_files {
+                if let Some(path) = &file.path {
+                    let (sender, receiver) = std::sync::mpsc::channel();
+                    self.file_loader_channel = Some(receiver);
+
+                    let path_clone = path.clone();
+                    thread::spawn(move || {
+                        if let anyhow::Result::Ok(bytes) = read_file_bytes(&path_clone) {
+                            sender.send((bytes, path_clone)).unwrap();
+                        } else {
+                            // TODO: failing cases.
+                        }
+                    });
+                }
+            }
+        })
+    }
+
+    /// Joins a room. Encrypted rooms are only joined once the passphrase is known,
+    /// until then the room menu asks for it.
+    pub fn join_room(&mut self, room: Room, passphrase: Option<String>) {
+        if !room.host.is_empty() && !room.hosted_by(&room.host) {
+            // Saved before join codes named their host
+            self.leave_room();
+            self.room_notice = Some(format!(
+                "Room {} has an outdated join code, create a new room",
+                room.code
+            ));
+            return;
+        }
+        let passphrase = passphrase.filter(|passphrase| !passphrase.is_empty());
+        self.room_problem = None;
+        self.room_notice = None;
+        let same_room = self.room.as_ref().map(|joined| &joined.code) == Some(&room.code);
+        let mut catch_up = false;
+        if !same_room {
+            self.roster = if self.is_local_peer(&room.host) {
+                Roster::new(&room.host)
+            } else {
+                Roster::default()
+            };
+            self.join_requests.clear();
+            // Asks for a role, or shares the roster, right away
+            self.roles_sent_at = f64::NEG_INFINITY;
+        }
+        if room.encrypted && passphrase.is_none() {
+            self.room_problem = Some(OpenError::PassphraseNeeded);
+            self.outbox.push_back(P2pCommand::LeaveRoom);
+        } else {
+            self.outbox.push_back(P2pCommand::JoinRoom {
+                room: room.clone(),
+                passphrase: passphrase.clone(),
+            });
+            // A room created here has nothing to catch up on
+            if same_room || !self.is_local_peer(&room.host) {
+                catch_up = true;
+            }
+        }
+        self.room = Some(room);
+        self.room_passphrase = passphrase;
+        self.presences.clear();
+        self.following = None;
+        self.catch_up = None;
+        self.catch_up_replies.clear();
+        self.share_roster();
+        if catch_up {
+            self.start_catch_up();
+        }
+    }
+
+    pub fn leave_room(&mut self) {
+        self.room_passphrase = None;
+        self.room_problem = None;
+        self.roster = Roster::default();
+        self.join_requests.clear();
+        self.presences.clear();
+        self.following = None;
+        self.presenting = false;
+        self.catch_up = None;
+        self.catch_up_replies.clear();
+        if self.room.take().is_some() {
+            self.outbox.push_back(P2pCommand::LeaveRoom);
+        }
+    }
+
+    fn ui_room_menu(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Room");
+
+        match self.room.clone() {
+            Some(room) => {
+                ui.horizontal(|ui| {
+                    if room.encrypted {
+                        ui.label("🔒").on_hover_text("Encrypted with a passphrase");
+                    }
+                    ui.label(&room.name);
+                    ui.monospace(room.display_code());
+                    if ui.small_button("Copy code").clicked() {
+                        ui.ctx().copy_text(room.display_code());
+                    }
+                });
+                if let Some(problem) = self.room_problem {
+                    ui.colored_label(ui.visuals().error_fg_color, problem.to_string());
+                    if problem != OpenError::Unencrypted {
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.passphrase_draft)
+                                    .password(true)
+                                    .hint_text("Passphrase")
+                                    .desired_width(120.0),
+                            );
+                            if ui.button("Unlock").clicked() {
+                                let passphrase = std::mem::take(&mut self.passphrase_draft);
+                                let room = Room {
+                                    encrypted: true,
+                                    ..room.clone()
+                                };
+                                self.join_room(room, Some(passphrase));
+                            }
+                        });
+                    }
+                }
+                self.ui_room_roles(ui, &room);
+                if ui.button("Leave room").clicked() {
+                    self.leave_room();
+                }
+            }
+            None => {
+                ui.label("Not in a room, nothing is synced");
+                if let Some(notice) = &self.room_notice {
+                    ui.colored_label(ui.visuals().warn_fg_color, notice);
+                }
+            }
+        }
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.room_name_draft)
+                    .hint_text("Room name")
+                    .desired_width(120.0),
+            );
+            if ui.button("Create").clicked() {
+                let passphrase = std::mem::take(&mut self.passphrase_draft);
+                let room = Room::create(
+                    &self.room_name_draft,
+                    !passphrase.is_empty(),
+                    self.identity.peer_id(),
+                );
+                self.room_name_draft.clear();
+                self.join_room(room, Some(passphrase));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.join_code_draft)
+                    .hint_text("Join code")
+                    .desired_width(120.0),
+            );
+            let code = normalize_code(&self.join_code_draft);
+            if ui
+                .add_enabled(code.is_some(), egui::Button::new("Join"))
+                .clicked()
+            {
+                if let Some(code) = code {
+                    let passphrase = std::mem::take(&mut self.passphrase_draft);
+                    let room = match self.discovered_rooms.get(&code) {
+                        Some((room, _)) => room.clone(),
+                        None => Room {
+                            name: format!("Room {code}"),
+                            code,
+                            encrypted: !passphrase.is_empty(),
+                            host: String::new(),
+                        },
+                    };
+                    self.join_code_draft.clear();
+                    self.join_room(room, Some(passphrase));
+                }
+            }
+        });
+
+        // Used by Create and Join, an empty passphrase makes an unencrypted room
+        if self.room_problem.is_none() {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.passphrase_draft)
+                    .password(true)
+                    .hint_text("Passphrase (optional)")
+                    .desired_width(120.0),
+            );
+        }
+
+        // Rooms stop being listed once nobody announces them any more
+        let now = ui.input(|i| i.time);
+        self.discovered_rooms
+            .retain(|_, (_, seen)| now - *seen < DISCOVERED_ROOM_TIMEOUT);
+
+        ui.label("Rooms nearby");
+        if self.discovered_rooms.is_empty() {
+            ui.weak(if self.menu_p2p_enabled {
+                "None found yet"
+            } else {
+                "Enable P2P to find rooms"
+            });
+        }
+        let mut rooms: Vec<Room> = self
+            .discovered_rooms
+            .values()
+            .map(|(room, _)| room.clone())
+            .collect();
+        rooms.sort_by(|a, b| (&a.name, &a.code).cmp(&(&b.name, &b.code)));
+        for room in rooms {
+            ui.horizontal(|ui| {
+                if room.encrypted {
+                    ui.label("🔒");
+                }
+                ui.label(&room.name);
+                ui.weak(room.display_code());
+                let current = self
+                    .room
+                    .as_ref()
+                    .is_some_and(|joined| joined.code == room.code);
+                if ui
+                    .add_enabled(!current, egui::Button::new("Join").small())
+                    .clicked()
+                {
+                    let passphrase = std::mem::take(&mut self.passphrase_draft);
+                    self.join_room(room, Some(passphrase));
+                }
+            });
+        }
+    }
+
+    pub fn send_state(&self) {
+        if !self.can_edit() {
+            return;
+        }
+        if let Some(sender) = &self.gui_sender {
+            let _a = sender
+                .try_send(P2pCommand::Broadcast(MessageType::CanvasState {
+                    state: SyncableState::from(self),
+                }))
+                .map_err(|err| println!("{:?}", err));
+        }
+    }
+
+    fn is_local_peer(&self, peer: &str) -> bool {
+        self.local_peer_id.as_deref() == Some(peer)
+    }
+
+    fn is_host(&self) -> bool {
+        self.room
+            .as_ref()
+            .is_some_and(|room| self.is_local_peer(&room.host))
+    }
+
+    /// Role in the current room. `None` while waiting for the host's approval.
+    pub fn local_role(&self) -> Option<Role> {
+        if self.is_host() {
+            return Some(Role::Host);
+        }
+        self.roster.role(self.local_peer_id.as_deref()?)
+    }
+
+    /// Whether local changes are allowed. Without a room the board is only ours.
+    pub fn can_edit(&self) -> bool {
+        self.room.is_none() || self.local_role().is_some_and(Role::can_edit)
+    }
+
+    fn peer_can_edit(&self, peer: &str) -> bool {
+        let Some(room) = &self.room else {
+            return false;
+        };
+        self.roster.can_edit(&room.host, peer)
+    }
+
+    fn receive_roster(&mut self, from: &str, roster: Roster) {
+        let Some(room) = &self.room else {
+            return;
+        };
+        if room.host.is_empty() || from != room.host || self.is_host() {
+            println!("Ignoring a roster from {from}, who is not the host");
+            return;
+        }
+        let was_member = self.local_role().is_some();
+        if !self.roster.merge(roster) {
+            return;
+        }
+        self.share_roster();
+        let local = self.local_peer_id.clone().unwrap_or_default();
+        if self.roster.is_banned(&local) {
+            self.leave_room();
+            self.room_notice = Some("The host removed you from the room".to_owned());
+        } else if !was_member && self.local_role().is_some() {
+            // Members did not answer while we waited for approval
+            self.start_catch_up();
+        }
+    }
+
+    fn receive_join_request(&mut self, from: String, name: String, now: f64) {
+        if !self.is_host() || self.roster.is_banned(&from) {
+            return;
+        }
+        if self.roster.role(&from).is_some() {
+            // Asked again, so it missed the roster
+            self.roles_sent_at = f64::NEG_INFINITY;
+            return;
+        }
+        self.join_requests.insert(from, (name, now));
+    }
+
+    fn set_role(&mut self, peer: &str, role: Role) {
+        self.roster.set_role(peer, role);
+        self.join_requests.remove(peer);
+        self.send_roster();
+    }
+
+    fn kick(&mut self, peer: &str) {
+        self.roster.remove(peer);
+        self.join_requests.remove(peer);
+        self.presences.remove(peer);
+        self.send_roster();
+    }
+
+    fn send_roster(&mut self) {
+        self.outbox
+            .push_back(P2pCommand::Broadcast(MessageType::Roster {
+                roster: self.roster.clone(),
+            }));
+        self.share_roster();
+    }
+
+    /// Tells the p2p thread who may fetch images and missed chunks.
+    fn share_roster(&mut self) {
+        let Some(room) = &self.room else {
+            return;
+        };
+        self.outbox.push_back(P2pCommand::Roster {
+            host: room.host.clone(),
+            roster: self.roster.clone(),
+        });
+    }
+
+    /// The host repeats the roster for peers that join later,
+    /// and peers without a role repeat their join request until the host answers.
+    fn update_roles(&mut self, ctx: &egui::Context) {
+        if self.room.is_none() || self.gui_sender.is_none() || self.room_problem.is_some() {
+            return;
+        }
+        let now = ctx.input(|i| i.time);
+        self.join_requests
+            .retain(|_, (_, asked)| now - *asked < JOIN_REQUEST_TIMEOUT);
+        if now - self.roles_sent_at >= ROLES_INTERVAL {
+            if self.is_host() {
+                self.send_roster();
+            } else if self.local_role().is_none() {
+                let name = self.identity.display_name.trim().to_owned();
+                self.outbox
+                    .push_back(P2pCommand::Broadcast(MessageType::JoinRequest { name }));
+            }
+            self.roles_sent_at = now;
+        }
+        ctx.request_repaint_after(std::time::Duration::from_secs_f64(ROLES_INTERVAL));
+    }
+
+    fn ui_room_roles(&mut self, ui: &mut egui::Ui, room: &Room) {
+        if self.room_problem.is_some() {
+            return;
+        }
+        match self.local_role() {
+            Some(role) => {
+                ui.label(format!("You are {}", role.to_string().to_lowercase()));
+            }
+            None if room.host.is_empty() => {
+                ui.weak("Looking for the host of the room");
+            }
+            None => {
+                ui.weak("Waiting for the host to let you in");
+            }
+        }
+        if !self.is_host() {
+            return;
+        }
+
+        let mut requests: Vec<(String, String)> = self
+            .join_requests
+            .iter()
+            .map(|(peer, (name, _))| (peer.clone(), name.clone()))
+            .collect();
+        requests.sort();
+        if !requests.is_empty() {
+            ui.label("Join requests");
+        }
+        for (peer, name) in requests {
+            ui.horizontal(|ui| {
+                let name = if name.is_empty() {
+                    short_peer_id(&peer).to_owned()
+                } else {
+                    name
+                };
+                ui.label(name)
+                    .on_hover_text(format!("{peer}\nFingerprint {}", fingerprint(&peer)));
+                if ui.small_button("Edit").clicked() {
+                    self.set_role(&peer, Role::Editor);
+                }
+                if ui.small_button("View only").clicked() {
+                    self.set_role(&peer, Role::Viewer);
+                }
+                if ui.small_button("Reject").clicked() {
+                    self.kick(&peer);
+                }
+            });
+        }
+
+        let mut members: Vec<(String, Role)> = self
+            .roster
+            .members
+            .iter()
+            .filter(|(_, role)| **role != Role::Host)
+            .map(|(peer, role)| (peer.clone(), *role))
+            .collect();
+        members.sort_by(|a, b| a.0.cmp(&b.0));
+        if !members.is_empty() {
+            ui.label("Members");
+        }
+        for (peer, role) in members {
+            ui.horizontal(|ui| {
+                let name = self
+                    .presences
+                    .get(&peer)
+                    .map(|(presence, _)| presence_name(&peer, presence).to_owned())
+                    .unwrap_or_else(|| short_peer_id(&peer).to_owned());
+                ui.label(name).on_hover_text(&peer);
+                let mut selected = role;
+                egui::ComboBox::from_id_salt(("member_role", &peer))
+                    .selected_text(selected.to_string())
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut selected, Role::Editor, "Editor");
+                        ui.selectable_value(&mut selected, Role::Viewer, "Viewer");
+                    });
+                if selected != role {
+                    self.set_role(&peer, selected);
+                }
+                if ui.small_button("Kick").clicked() {
+                    self.kick(&peer);
+                }
+            });
+        }
+    }
+
+    /// Shares the pointer and viewport with the room, at most every `PRESENCE_INTERVAL`.
+    fn send_presence(&mut self, ctx: &egui::Context) {
+        let Some(sender) = &self.gui_sender else {
+            return;
+        };
+        // Board changes go first, presence only fills the gaps
+        if self.room.is_none() || !self.outbox.is_empty() {
+            return;
+        }
+
+        let now = ctx.input(|i| i.time);
+        let to_canvas = self.transform.inverse();
+        let viewport = to_canvas * ctx.screen_rect();
+        let presence = Presence {
+            name: self.identity.display_name.trim().to_owned(),
+            color: self.identity.color,
+            cursor: ctx.pointer_hover_pos().map(|pos| {
+                let pos = to_canvas * pos;
+                [pos.x, pos.y]
+            }),
+            viewport: [
+                [viewport.min.x, viewport.min.y],
+                [viewport.max.x, viewport.max.y],
+            ],
+            zoom: self.transform.scaling,
+            selection: self.selected_image,
+            presenting: self.presenting,
+            following: self.following.clone(),
+        };
+        let due = match &self.last_presence {
+            Some((last, sent_at)) => {
+                (*last != presence && now - sent_at >= PRESENCE_INTERVAL)
+                    || now - sent_at >= PRESENCE_KEEPALIVE
+            }
+            None => true,
+        };
+        let sent = due
+            && sender
+                .try_send(P2pCommand::Presence(presence.clone()))
+                .is_ok();
+        // Comes back for the final position of a pointer that stopped between updates,
+        // and keeps peers from timing out an idle window
+        let unsent = !sent
+            && self
+                .last_presence
+                .as_ref()
+                .is_some_and(|(last, _)| *last != presence);
+        let next = if unsent {
+            PRESENCE_INTERVAL
+        } else {
+            PRESENCE_KEEPALIVE
+        };
+        if sent {
+            self.last_presence = Some((presence, now));
+        }
+        ctx.request_repaint_after(std::time::Duration::from_secs_f64(next));
+    }
+
+    /// Paints the other peers' pointers, and their viewports if enabled.
+    fn paint_presences(&mut self, ctx: &egui::Context) {
+        use egui::{vec2, Align2, Color32, FontId, Id, LayerId, Order, Stroke};
+
+        let now = ctx.input(|i| i.time);
+        self.presences
+            .retain(|_, (_, seen)| now - *seen < PRESENCE_TIMEOUT);
+        if self.presences.is_empty() {
+            return;
+        }
+
+        let painter = ctx.layer_painter(LayerId::new(Order::Foreground, Id::new("presence")));
+        let font = FontId::proportional(12.0);
+        for (peer, (presence, _)) in &self.presences {
+            let [r, g, b] = presence.color;
+            let color = egui::Color32::from_rgb(r, g, b);
+            let name = presence_name(peer, presence);
+
+            if self.show_viewports {
+                let [min, max] = presence.viewport;
+                let rect = self.transform * egui::Rect::from_min_max(min.into(), max.into());
+                painter.rect_stroke(rect, 0.0, Stroke::new(1.5, color));
+                painter.text(
+                    rect.left_top() + vec2(4.0, 2.0),
+                    Align2::LEFT_TOP,
+                    name,
+                    font.clone(),
+                    color,
+                );
+            }
+
+            if let Some(cursor) = presence.cursor {
+                let tip = self.transform * egui::Pos2::from(cursor);
+                painter.add(egui::Shape::convex_polygon(
+                    vec![tip, tip + vec2(0.0, 15.0), tip + vec2(10.0, 10.0)],
+                    color,
+                    Stroke::new(1.0, Color32::BLACK),
+                ));
+                let galley = painter.layout_no_wrap(name.to_owned(), font.clone(), Color32::BLACK);
+                let label = egui::Rect::from_min_size(
+                    tip + vec2(10.0, 14.0),
+                    galley.size() + vec2(8.0, 4.0),
+                );
+                painter.rect_filled(label, 3.0, color);
+                painter.galley(label.min + vec2(4.0, 2.0), galley, Color32::BLACK);
+            }
+        }
+        // Remote pointers move without any local input
+        ctx.request_repaint_after(std::time::Duration::from_secs_f64(PRESENCE_INTERVAL));
+    }
+
+    /// Mirrors the camera and selection of the followed peer. The presenter's zoom is kept,
+    /// and the centre of their view is put in the centre of this window.
+    fn follow_presenter(&mut self, ctx: &egui::Context) {
+        let Some(peer) = &self.following else {
+            return;
+        };
+        let now = ctx.input(|i| i.time);
+        let presenting = self
+            .presences
+            .get(peer)
+            .filter(|(presence, seen)| presence.presenting && now - *seen < PRESENCE_TIMEOUT);
+        let Some((presence, _)) = presenting else {
+            // The presenter stopped presenting, left or timed out
+            self.following = None;
+            return;
+        };
+        let [min, max] = presence.viewport;
+        let center = egui::Rect::from_min_max(min.into(), max.into()).center();
+        let scaling = presence.zoom.clamp(0.01, 100.0);
+        let translation = ctx.screen_rect().center().to_vec2() - center.to_vec2() * scaling;
+        self.transform = TSTransform::new(translation, scaling);
+        self.selected_image = presence
+            .selection
+            .filter(|id| self.board.image(*id).is_some());
+    }
+
+    /// Names of the peers following this one.
+    fn followers(&self) -> Vec<String> {
+        let Some(local) = &self.local_peer_id else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = self
+            .presences
+            .iter()
+            .filter(|(_, (presence, _))| presence.following.as_ref() == Some(local))
+            .map(|(peer, (presence, _))| presence_name(peer, presence).to_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Top bar controls for presenting and following.
+    fn ui_presenting_bar(&mut self, ui: &mut egui::Ui) {
+        if self.room.is_none() || !self.p2p_running.load(Ordering::Relaxed) {
+            return;
+        }
+        ui.separator();
+
+        if let Some(peer) = self.following.clone() {
+            let name = match self.presences.get(&peer) {
+                Some((presence, _)) => presence_name(&peer, presence).to_owned(),
+                None => short_peer_id(&peer).to_owned(),
+            };
+            ui.label(format!("Following {name}"));
+            if ui.button("Stop following").clicked() {
+                self.following = None;
+            }
+            return;
+        }
+
+        if self.presenting {
+            let followers = self.followers();
+            ui.label(format!("Presenting, {} following", followers.len()))
+                .on_hover_text(if followers.is_empty() {
+                    "Nobody is following yet".to_owned()
+                } else {
+                    followers.join("\n")
+                });
+            if ui.button("Stop presenting").clicked() {
+                self.presenting = false;
+            }
+            return;
+        }
+
+        let mut presenters: Vec<(String, String)> = self
+            .presences
+            .iter()
+            .filter(|(_, (presence, _))| presence.presenting)
+            .map(|(peer, (presence, _))| (presence_name(peer, presence).to_owned(), peer.clone()))
+            .collect();
+        presenters.sort();
+        for (name, peer) in presenters {
+            if ui
+                .button(format!("Follow {name}"))
+                .on_hover_text(format!("{name} is presenting"))
+                .clicked()
+            {
+                self.following = Some(peer);
+            }
+        }
+        if ui.button("Present").clicked() {
+            self.presenting = true;
+        }
+    }
+
+    /// Handles everything the p2p thread sent since the last frame.
+    pub fn handle_p2p_messages(&mut self, ctx: &egui::Context) {
+        loop {
+            let Some(p2p) = &mut self.p2p_receiver else {
+                return;
+            };
+            let anyhow::Result::Ok(event) = p2p.try_recv() else {
+                return;
+            };
+            let message = match event {
+                P2pEvent::LocalPeerId(peer_id) => {
+                    self.local_peer_id = Some(peer_id);
+                    continue;
+                }
+                P2pEvent::BlobReceived(hash) => {
+                    // Images waiting for these bytes show up on the next frame
+                    println!("Received image {hash}");
+                    ctx.request_repaint();
+                    continue;
+                }
+                P2pEvent::Transfer(event) => {
+                    if let TransferEvent::Failed { id, error } = &event {
+                        println!("Transfer {id} failed: {error}");
+                    }
+                    if let TransferEvent::Progress {
+                        received, total, ..
+                    } = &event
+                    {
+                        // Most likely the snapshot, nothing else is that large
+                        if let Some(catch_up) = &mut self.catch_up {
+                            catch_up.chunks = Some((*received, *total));
+                        }
+                    }
+                    self.last_transfer = Some(event);
+                    continue;
+                }
+                P2pEvent::RoomAnnounced(room) => {
+                    // The code names its host, so a known host never changes
+                    if !room.hosted_by(&room.host) {
+                        continue;
+                    }
+                    let now = ctx.input(|i| i.time);
+                    // Rooms joined by code learn their host from the announcements
+                    if let Some(joined) = &mut self.room {
+                        if joined.code == room.code && joined.host.is_empty() {
+                            joined.host = room.host.clone();
+                            self.share_roster();
+                        }
+                    }
+                    self.discovered_rooms.insert(room.code.clone(), (room, now));
+                    continue;
+                }
+                P2pEvent::Presence { peer, presence } => {
+                    if self.roster.is_banned(&peer) {
+                        continue;
+                    }
+                    let now = ctx.input(|i| i.time);
+                    self.presences.insert(peer, (presence, now));
+                    continue;
+                }
+                P2pEvent::Peers(peers) => {
+                    let was_connected = self.peers.iter().any(|peer| peer.connected);
+                    self.peers = peers;
+                    self.name_known_peers();
+                    let connected = self.peers.iter().any(|peer| peer.connected);
+                    // A working connection means earlier errors no longer apply
+                    if connected {
+                        self.network_error = None;
+                    }
+                    // Back online, changes made meanwhile are fetched
+                    if !was_connected && connected {
+                        self.start_catch_up();
+                    }
+                    continue;
+                }
+                P2pEvent::Invite(invite) => {
+                    // Listening on at least one address
+                    if !invite.is_empty() {
+                        self.network_error = None;
+                    }
+                    self.invite = Some(invite);
+                    continue;
+                }
+                P2pEvent::NetworkError(error) => {
+                    println!("{error}");
+                    self.network_error = Some(error);
+                    continue;
+                }
+                P2pEvent::Decryption(problem) => {
+                    println!("Room data could not be read: {problem}");
+                    self.room_problem = Some(problem);
+                    continue;
+                }
+                P2pEvent::Message { from, message } => {
+                    if self.room_problem != Some(OpenError::PassphraseNeeded) {
+                        self.room_problem = None;
+                    }
+                    (from, message)
+                }
+            };
+            let (from, message) = message;
+
+            let message = match message {
+                MessageType::Roster { roster } => {
+                    self.receive_roster(&from, roster);
+                    continue;
+                }
+                MessageType::JoinRequest { name } => {
+                    let now = ctx.input(|i| i.time);
+                    self.receive_join_request(from, name, now);
+                    continue;
+                }
+                MessageType::CatchUpRequest { request } => {
+                    self.receive_catch_up_request(ctx, &from, request);
+                    continue;
+                }
+                message => message,
+            };
+            if !self.peer_can_edit(&from) {
+                println!("Ignoring a change from {from}, who may not edit");
+                continue;
+            }
+
+            match message {
+                MessageType::CatchUp { request, state } => {
+                    self.receive_catch_up(ctx, request, state);
+                }
+                message => self.apply_message(ctx, message),
+            }
+        }
+    }
+
+    fn apply_message(&mut self, ctx: &egui::Context, message: MessageType) {
+        match message {
+            MessageType::Operation { operation } => {
+                self.board.apply(&operation);
+                match operation.kind {
+                    OperationKind::Delete => self.forget_removed_images(),
+                    OperationKind::Add { .. }
+                    | OperationKind::Move { .. }
+                    | OperationKind::Transform { .. }
+                    | OperationKind::Reorder { .. }
+                    | OperationKind::Describe { .. } => {}
+                    _ => self.refresh_items(),
+                }
+            }
+            MessageType::CanvasState { state } => {
+                self.apply_state(state);
+            }
+            MessageType::Playback { update } => {
+                let now = ctx.input(|i| i.time);
+                self.board.witness(update.stamp);
+                self.playbacks
+                    .entry(update.image)
+                    .or_default()
+                    .apply_remote(&update, now);
+                ctx.request_repaint();
+            }
+            // Handled in `handle_p2p_messages`
+            MessageType::Roster { .. }
+            | MessageType::JoinRequest { .. }
+            | MessageType::CatchUpRequest { .. }
+            | MessageType::CatchUp { .. } => {}
+        }
+    }
+
+    fn waiting_for_snapshot(&self) -> bool {
+        self.catch_up
+            .as_ref()
+            .is_some_and(|catch_up| catch_up.snapshot_at.is_none())
+    }
+
+    /// Asks the room for the current board. Sent by `update_catch_up`.
+    fn start_catch_up(&mut self) {
+        if self.room.is_none() || self.catch_up.is_some() {
+            return;
+        }
+        self.catch_up = Some(CatchUp {
+            request: rand::random(),
+            asked_at: f64::NEG_INFINITY,
+            attempts: 0,
+            chunks: None,
+            snapshot_at: None,
+        });
+    }
+
+    /// Every peer that can answer schedules a reply. The host answers right away, others after a
+    /// random delay, and replies are dropped once someone else has answered.
+    /// Only the host and approved members are answered, the snapshot is the whole board.
+    fn receive_catch_up_request(&mut self, ctx: &egui::Context, from: &str, request: u64) {
+        if self.waiting_for_snapshot() || !self.local_role().is_some_and(Role::can_edit) {
+            return;
+        }
+        let Some(room) = &self.room else {
+            return;
+        };
+        if from != room.host && !self.roster.admits(from) {
+            println!("Ignoring a catch-up request from {from}, who is not a member");
+            return;
+        }
+        let delay = if self.is_host() {
+            0.0
+        } else {
+            rand::Rng::gen_range(&mut rand::thread_rng(), CATCH_UP_REPLY_DELAY)
+        };
+        let now = ctx.input(|i| i.time);
+        self.catch_up_replies.entry(request).or_insert(now + delay);
+    }
+
+    fn receive_catch_up(&mut self, ctx: &egui::Context, request: u64, state: SyncableState) {
+        self.catch_up_replies.remove(&request);
+        let Some(catch_up) = &mut self.catch_up else {
+            return;
+        };
+        if catch_up.request != request || catch_up.snapshot_at.is_some() {
+            return;
+        }
+        catch_up.snapshot_at = Some(ctx.input(|i| i.time));
+        println!("Caught up");
+
+        // Merged like any other state, so live changes received meanwhile are kept
+        let received = state.board.clone();
+        self.apply_state(state);
+        // Changes made while offline are missing from the snapshot
+        let board = &self.board;
+        let ahead = board.images != received.images
+            || board.items != received.items
+            || board.pins != received.pins;
+        if ahead && self.can_edit() {
+            self.outbox
+                .push_back(P2pCommand::Broadcast(MessageType::CanvasState {
+                    state: SyncableState::from(&*self),
+                }));
+        }
+    }
+
+    /// Sends catch-up requests and replies, and ends catching up once images have arrived.
+    fn update_catch_up(&mut self, ctx: &egui::Context) {
+        if self.gui_sender.is_none() || self.room_problem.is_some() {
+            return;
+        }
+        let now = ctx.input(|i| i.time);
+
+        let due: Vec<u64> = self
+            .catch_up_replies
+            .iter()
+            .filter(|(_, due)| now >= **due)
+            .map(|(request, _)| *request)
+            .collect();
+        for request in due {
+            self.catch_up_replies.remove(&request);
+            self.outbox
+                .push_back(P2pCommand::Broadcast(MessageType::CatchUp {
+                    request,
+                    state: SyncableState::from(&*self),
+                }));
+        }
+
+        let missing_images = self.missing_images();
+        let Some(catch_up) = &mut self.catch_up else {
+            if !self.catch_up_replies.is_empty() {
+                ctx.request_repaint_after(std::time::Duration::from_millis(100));
+            }
+            return;
+        };
+        match catch_up.snapshot_at {
+            None if now - catch_up.asked_at >= CATCH_UP_RETRY => {
+                if catch_up.attempts >= CATCH_UP_ATTEMPTS {
+                    // Nobody else is in the room
+                    println!("No peer answered, nothing to catch up on");
+                    self.catch_up = None;
+                    return;
+                }
+                catch_up.attempts += 1;
+                catch_up.asked_at = now;
+                let request = catch_up.request;
+                self.outbox
+                    .push_back(P2pCommand::Broadcast(MessageType::CatchUpRequest {
+                        request,
+                    }));
+            }
+            Some(snapshot_at)
+                if missing_images.0 == 0 || now - snapshot_at >= CATCH_UP_IMAGE_WAIT =>
+            {
+                self.catch_up = None;
+                return;
+            }
+            _ => {}
+        }
+        ctx.request_repaint_after(std::time::Duration::from_millis(100));
+    }
+
+    /// Images on the board whose bytes have not arrived yet, and all images.
+    fn missing_images(&self) -> (usize, usize) {
+        let images = self.board.ordered();
+        let missing = images
+            .iter()
+            .filter(|(_, image)| !self.blobs.contains(&image.info.value.content_hash))
+            .count();
+        (missing, images.len())
+    }
+
+    fn ui_catch_up_progress(&self, ui: &mut egui::Ui) {
+        let Some(catch_up) = &self.catch_up else {
+            return;
+        };
+        ui.separator();
+        match (catch_up.snapshot_at, catch_up.chunks) {
+            (None, Some((received, total))) => {
+                ui.add(
+                    egui::ProgressBar::new(received as f32 / total as f32)
+                        .desired_width(120.0)
+                        .text(format!("Receiving board {received}/{total}")),
+                );
+            }
+            (None, None) => {
+                ui.spinner();
+                ui.label("Catching up");
+            }
+            (Some(_), _) => {
+                let (missing, total) = self.missing_images();
+                let done = total - missing;
+                ui.add(
+                    egui::ProgressBar::new(done as f32 / total.max(1) as f32)
+                        .desired_width(120.0)
+                        .text(format!("Images {done}/{total}")),
+                );
+            }
+        }
+    }
+
+    pub fn start_network_sync(&mut self) {
+        if self.p2p_running.load(Ordering::Relaxed) {
+            return;
+        }
+
+        self.p2p_running.store(true, Ordering::Relaxed);
+
+        let (gui_sender, gui_receiver) = mpsc::channel::<P2pCommand>(1);
+        let (p2p_sender, p2p_receiver) = mpsc::channel::<P2pEvent>(1);
+        let p2p_running = Arc::clone(&self.p2p_running);
+        let blobs = self.blobs.clone();
+        let keypair = self.identity.keypair.clone();
+        let display_name = self.identity.display_name.trim().to_owned();
+        self.network_error = None;
+
+        self.p2p_receiver = Some(p2p_receiver);
+        self.gui_sender = Some(gui_sender);
+        if let Some(room) = self.room.clone() {
+            self.join_room(room, self.room_passphrase.clone());
+        }
+        for known in &self.known_peers {
+            match Invite::parse(&known.invite) {
+                anyhow::Result::Ok(invite) => self.outbox.push_back(P2pCommand::Dial(invite)),
+                Err(err) => println!("Ignoring known peer: {err:?}"),
+            }
+        }
+
+        let handle = std::thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(p2p::p2p(
+                gui_receiver,
+                p2p_sender,
+                p2p_running,
+                blobs,
+                keypair,
+                display_name,
+            ));
+        });
+
+        self.p2p_thread_handle = Some(handle);
+    }
+
+    pub fn stop_network_sync(&mut self) {
+        if !self.p2p_running.load(Ordering::Relaxed) {
+            return;
+        }
+
+        self.p2p_running.store(false, Ordering::Relaxed);
+
+        self.p2p_receiver = None;
+        self.gui_sender = None;
+        self.peers.clear();
+        self.invite = None;
+        self.presences.clear();
+        self.last_presence = None;
+        self.following = None;
+        self.presenting = false;
+        self.catch_up = None;
+        self.catch_up_replies.clear();
+    }
+
+    fn save_identity(&self) {
+        if let Err(err) = self.identity.save() {
+            println!("Failed to save identity: {err:?}");
+        }
+    }
+
+    /// Switches to an exported identity. The network restarts, since the peer ID changes.
+    fn import_identity(&mut self) {
+        let identity = match Identity::import(&PathBuf::from(&self.identity_path)) {
+            anyhow::Result::Ok(identity) => identity,
+            Err(err) => {
+                println!("Failed to import identity: {err:?}");
+                return;
+            }
+        };
+        self.local_peer_id = Some(identity.peer_id());
+        self.identity = identity;
+        self.save_identity();
+
+        self.stop_network_sync();
+        // The old p2p thread keeps its flag, and exits even though a new one starts
+        self.p2p_running = Arc::new(AtomicBool::new(false));
+    }
+
+    /// Dials a pasted invite, and remembers the peer for the next launches.
+    fn connect(&mut self) {
+        let invite = match Invite::parse(&self.connect_draft) {
+            anyhow::Result::Ok(invite) => invite,
+            Err(err) => {
+                self.connect_error = Some(err.to_string());
+                return;
+            }
+        };
+        self.connect_error = None;
+        self.connect_draft.clear();
+
+        let peer_id = invite.peer_id.to_string();
+        self.known_peers.retain(|known| {
+            Invite::parse(&known.invite).map_or(true, |old| old.peer_id != invite.peer_id)
+        });
+        self.known_peers.push(KnownPeer {
+            invite: invite.to_string(),
+            name: self
+                .peers
+                .iter()
+                .find(|peer| peer.peer_id == peer_id)
+                .and_then(|peer| peer.name.clone()),
+        });
+        self.save_known_peers();
+        if self.gui_sender.is_some() {
+            self.outbox.push_back(P2pCommand::Dial(invite));
+        }
+    }
+
+    fn forget_known_peer(&mut self, index: usize) {
+        let known = self.known_peers.remove(index);
+        if let anyhow::Result::Ok(invite) = Invite::parse(&known.invite) {
+            if self.gui_sender.is_some() {
+                self.outbox.push_back(P2pCommand::Forget(invite.peer_id));
+            }
+        }
+        self.save_known_peers();
+    }
+
+    /// Known peers show the name they last sent, even while offline.
+    fn name_known_peers(&mut self) {
+        let mut changed = false;
+        for known in &mut self.known_peers {
+            let Some(peer_id) = known.invite.rsplit("/p2p/").next() else {
+                continue;
+            };
+            let name = self
+                .peers
+                .iter()
+                .find(|peer| peer.peer_id == peer_id)
+                .and_then(|peer| peer.name.clone());
+            if name.is_some() && name != known.name {
+                known.name = name;
+                changed = true;
+            }
+        }
+        if changed {
+            self.save_known_peers();
+        }
+    }
+
+    fn save_known_peers(&self) {
+        if let Err(err) = save_known_peers(&self.known_peers) {
+            println!("Failed to save known peers: {err:?}");
+        }
+    }
+
+    fn ui_connect_menu(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Connect");
+
+        ui.horizontal(|ui| {
+            let has_invite = self.invite.is_some();
+            if ui
+                .add_enabled(has_invite, egui::Button::new("Invite"))
+                .on_hover_text("Show our addresses for peers that mDNS cannot find")
+                .clicked()
+            {
+                self.show_invite = true;
+            }
+            if ui
+                .add_enabled(has_invite, egui::Button::new("Copy invite"))
+                .clicked()
+            {
+                if let Some(invite) = &self.invite {
+                    ui.ctx().copy_text(invite.clone());
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.connect_draft)
+                    .hint_text("Paste an invite")
+                    .desired_width(160.0),
+            );
+            if ui
+                .add_enabled(
+                    !self.connect_draft.trim().is_empty(),
+                    egui::Button::new("Connect"),
+                )
+                .clicked()
+            {
+                self.connect();
+            }
+        });
+        if let Some(error) = &self.connect_error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+
+        if self.known_peers.is_empty() {
+            return;
+        }
+        ui.label("Known peers");
+        let mut forget = None;
+        for (index, known) in self.known_peers.iter().enumerate() {
+            ui.horizontal(|ui| {
+                let peer_id = known.invite.rsplit("/p2p/").next().unwrap_or_default();
+                let connected = self
+                    .peers
+                    .iter()
+                    .any(|peer| peer.connected && peer.peer_id == peer_id);
+                let name = known.name.as_deref().unwrap_or(short_peer_id(peer_id));
+                if connected {
+                    ui.label(name)
+                } else {
+                    ui.weak(name)
+                }
+                .on_hover_text(&known.invite);
+                if ui.small_button("Forget").clicked() {
+                    forget = Some(index);
+                }
+            });
+        }
+        if let Some(index) = forget {
+            self.forget_known_peer(index);
+        }
+    }
+
+    fn ui_invite_window(&mut self, ctx: &egui::Context) {
+        let Some(invite) = &self.invite else {
+            self.show_invite = false;
+            return;
+        };
+        egui::Window::new("Invite")
+            .open(&mut self.show_invite)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("Scan or paste this into Connect on the other machine");
+                ui_qr_code(ui, invite);
+                ui.add(egui::Label::new(egui::RichText::new(invite).monospace()).wrap());
+                if ui.button("Copy").clicked() {
+                    ui.ctx().copy_text(invite.clone());
+                }
+            });
+    }
+
+    pub fn network_status(&self) -> NetworkStatus {
+        if !self.p2p_running.load(Ordering::Relaxed) {
+            NetworkStatus::Off
+        } else if self.network_error.is_some() {
+            NetworkStatus::Error
+        } else if self.peers.iter().any(|peer| peer.connected) {
+            NetworkStatus::Connected
+        } else {
+            NetworkStatus::Searching
+        }
+    }
+
+    fn ui_status_light(&self, ui: &mut egui::Ui) {
+        let status = self.network_status();
+        let (rect, response) = ui.allocate_exact_size(egui::vec2(12.0, 12.0), egui::Sense::hover());
+        ui.painter()
+            .circle_filled(rect.center(), 5.0, status.color());
+        let connected = self.peers.iter().filter(|peer| peer.connected).count();
+        response.on_hover_text(match status {
+            NetworkStatus::Off => "P2P is off".to_owned(),
+            NetworkStatus::Searching => "Searching for peers".to_owned(),
+            NetworkStatus::Connected => format!("Connected to {connected} peer(s)"),
+            NetworkStatus::Error => self.network_error.clone().unwrap_or_default(),
+        });
+    }
+
+    fn ui_peer_list(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Peers");
+        if self.peers.is_empty() {
+            ui.weak(if self.menu_p2p_enabled {
+                "No peers found yet"
+            } else {
+                "Enable P2P to find peers"
+            });
+            return;
+        }
+        Grid::new("peer_grid").striped(true).show(ui, |ui| {
+            ui.strong("Name");
+            ui.strong("Peer");
+            ui.strong("Address");
+            ui.strong("Via");
+            ui.strong("Ping");
+            ui.strong("");
+            ui.end_row();
+
+            for peer in &self.peers {
+                // Presence carries the current name, identify only the one at connection time
+                let name = self
+                    .presences
+                    .get(&peer.peer_id)
+                    .map(|(presence, _)| presence.name.as_str())
+                    .filter(|name| !name.is_empty())
+                    .or(peer.name.as_deref())
+                    .unwrap_or("Unnamed");
+                if peer.connected {
+                    ui.label(name);
+                } else {
+                    ui.weak(name)
+                        .on_hover_text("Found on the network, not connected yet");
+                }
+                ui.monospace(short_peer_id(&peer.peer_id))
+                    .on_hover_text(format!(
+                        "{}\nFingerprint {}",
+                        peer.peer_id,
+                        fingerprint(&peer.peer_id)
+                    ));
+                ui.label(&peer.address);
+                ui.label(peer.transport.to_string());
+                match peer.latency {
+                    Some(latency) => ui.label(format!("{} ms", latency.as_millis())),
+                    None => ui.label("-"),
+                };
+                // Presenting peers in the same room can be followed
+                let following = self.following.as_ref() == Some(&peer.peer_id);
+                let presenting = self
+                    .presences
+                    .get(&peer.peer_id)
+                    .is_some_and(|(presence, _)| presence.presenting);
+                if presenting
+                    && ui
+                        .add_enabled(!following, egui::Button::new("Follow").small())
+                        .clicked()
+                {
+                    self.following = Some(peer.peer_id.clone());
+                }
+                ui.end_row();
+            }
+        });
+    }
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if self.menu_p2p_enabled {
+            self.start_network_sync();
+        } else {
+            self.stop_network_sync();
+        }
+
+        TopBottomPanel::top("top_panel").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Menu").clicked() {
+                    self.show_menu_panel = !self.show_menu_panel;
+                }
+                if ui.button("Add text").clicked() {
+                    self.add_text_item(ctx);
+                }
+                self.ui_status_light(ui);
+                self.ui_catch_up_progress(ui);
+                self.ui_presenting_bar(ui);
+                self.ui_tool_bar(ui);
+                self.ui_search_bar(ui);
+            })
+        });
+
+        if self.show_menu_panel {
+            SidePanel::left("menu_panel").show(ctx, |ui| {
+                ui.heading("Menu");
+                ui.separator();
+
+                Grid::new("menu_grid").show(ui, |ui| {
+                    ui.label("P2P server");
+                    ui.add(toggle(&mut self.menu_p2p_enabled));
+                    ui.end_row();
+
+                    ui.label("P2P status");
+                    ui.horizontal(|ui| {
+                        self.ui_status_light(ui);
+                        match &self.network_error {
+                            Some(error) => {
+                                ui.colored_label(ui.visuals().error_fg_color, error);
+                            }
+                            None => {
+                                ui.label(format!("{:?}", self.network_status()));
+                            }
+                        }
+                    });
+                    ui.end_row();
+
+                    if let Some(error) = &self.identity.load_error {
+                        ui.label("Identity");
+                        ui.colored_label(ui.visuals().error_fg_color, error)
+                            .on_hover_text(
+                                "This identity is temporary and not saved.\n\
+                                 Import an exported one to replace the file.",
+                            );
+                        ui.end_row();
+                    }
+
+                    ui.label("Display name");
+                    ui.horizontal(|ui| {
+                        let name = ui
+                            .add(
+                                egui::TextEdit::singleline(&mut self.identity.display_name)
+                                    .hint_text("Unnamed")
+                                    .desired_width(120.0),
+                            )
+                            .on_hover_text(
+                                "Peers in the room see a new name right away, others once P2P restarts",
+                            );
+                        let color = ui.color_edit_button_srgb(&mut self.identity.color);
+                        if name.lost_focus() || color.changed() {
+                            self.save_identity();
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Fingerprint");
+                    let local_peer_id = self.identity.peer_id();
+                    ui.horizontal(|ui| {
+                        ui.monospace(fingerprint(&local_peer_id))
+                            .on_hover_text(format!(
+                                "Peer ID {local_peer_id}\nCompare with what other peers see"
+                            ));
+                        if ui.small_button("Copy ID").clicked() {
+                            ui.ctx().copy_text(local_peer_id.clone());
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Identity file");
+                    ui.text_edit_singleline(&mut self.identity_path);
+                    ui.end_row();
+
+                    ui.label("");
+                    ui.horizontal(|ui| {
+                        if ui
+                            .button("Export")
+                            .on_hover_text("Includes the private key, keep it safe")
+                            .clicked()
+                        {
+                            if let Err(err) =
+                                self.identity.export(&PathBuf::from(&self.identity_path))
+                            {
+                                println!("Failed to export identity: {err:?}");
+                            }
+                        }
+                        if ui
+                            .button("Import")
+                            .on_hover_text("Replaces the current identity")
+                            .clicked()
+                        {
+                            self.import_identity();
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Peer viewports");
+                    ui.checkbox(&mut self.show_viewports, "Show");
+                    ui.end_row();
+
+                    ui.label("Last transfer");
+                    match &self.last_transfer {
+                        Some(TransferEvent::Completed { id, size }) => {
+                            ui.label(format!("Received {}", format_file_size(*size)))
+                                .on_hover_text(format!("Message {id:016x}"));
+                        }
+                        Some(TransferEvent::Progress {
+                            id,
+                            received,
+                            total,
+                        }) => {
+                            ui.label(format!("Receiving {received}/{total} chunks"))
+                                .on_hover_text(format!("Message {id:016x}"));
+                        }
+                        Some(TransferEvent::Failed { error, .. }) => {
+                            ui.colored_label(ui.visuals().error_fg_color, "Failed")
+                                .on_hover_text(error);
+                        }
+                        None => {
+                            ui.label("-");
+                        }
+                    }
+                    ui.end_row();
+
+                    ui.label("Manual state sync");
+                    if ui.add(egui::Button::new("Send state")).clicked() {
+                        self.send_state();
+                    }
+                    ui.end_row();
+
+                    ui.label("Board file");
+                    ui.text_edit_singleline(&mut self.board_path);
+                    ui.end_row();
+
+                    ui.label("");
+                    ui.horizontal(|ui| {
+                        if ui.button("Save").clicked() {
+                            self.save_board();
+                        }
+                        if ui.button("Open").clicked() {
+                            self.load_board();
+                        }
+                    });
+                    ui.end_row();
+                });
+
+                ui.separator();
+                self.ui_room_menu(ui);
+
+                ui.separator();
+                self.ui_connect_menu(ui);
+
+                ui.separator();
+                self.ui_peer_list(ui);
+            });
+        }
+
+        self.follow_presenter(ctx);
+        self.ui_image_inspector(ctx);
+        self.update_search();
+
+        // CANVAS
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let rect = ui.min_rect();
+            let window_layer = ui.layer_id();
+            self.manage_canvas_movement(ui);
+
+            for image in self.images.iter_mut() {
+                let i = canvas_image(image);
+                ui.add(i);
+            }
+
+            // Painted into the canvas layer in z order, so later images are on top and win hit tests
+            let ordered: Vec<ItemId> = self.board.ordered().into_iter().map(|(id, _)| id).collect();
+            for image_id in ordered {
+                let hit = SearchHit::Image(image_id);
+                if self.filtered_out(hit) {
+                    self.image_rects.remove(&image_id);
+                    continue;
+                }
+                let Some(image) = self.board.image(image_id) else {
+                    continue;
+                };
+                // Still being fetched from a peer
+                let Some(bytes) = self.blobs.get(&image.info.value.content_hash) else {
+                    continue;
+                };
+                let position = egui::pos2(image.position.value[0], image.position.value[1]);
+                let scale = image.transform.value.scale;
+
+                let uri = image_uri(image_id);
+                let e_bytes = egui::load::Bytes::Shared(bytes);
+
+                // Animated images are driven frame by frame, so that playback can be paused and synced.
+                let widget = if egui::has_gif_magic_header(&e_bytes) {
+                    ctx.include_bytes(uri.clone(), e_bytes);
+                    let frame = self.animation_frame(ctx, &uri, image_id);
+                    egui::Image::from_uri(format!("{uri}#{frame}"))
+                } else {
+                    match self.display_image(ctx, image_id) {
+                        DisplayImage::Decoding(_) => continue,
+                        DisplayImage::Original => egui::Image::from_bytes(uri, e_bytes),
+                        DisplayImage::Corrected(texture) => egui::Image::from_texture(
+                            egui::load::SizedTexture::from_handle(texture),
+                        ),
+                    }
+                }
+                .sense(egui::Sense::click_and_drag())
+                .fit_to_original_size(scale);
+                let widget = if self.search.is_match(hit) {
+                    widget
+                } else {
+                    widget.tint(egui::Color32::from_white_alpha(
+                        (DIMMED_OPACITY * 255.0) as u8,
+                    ))
+                };
+
+                // Still loading
+                let Some(size) = widget.load_and_calc_size(ui, egui::Vec2::INFINITY) else {
+                    continue;
+                };
+                let canvas_rect = egui::Rect::from_min_size(position, size);
+                let screen_rect = self.transform * canvas_rect;
+
+                let source_size = self
+                    .overlays
+                    .get(&image_id)
+                    .and_then(|overlays| overlays.ruler)
+                    .and_then(|_| self.source_size(image_id));
+                let widget = overlay_image(
+                    widget.fit_to_exact_size(screen_rect.size()),
+                    self.overlays.get(&image_id),
+                    source_size,
+                    1.0,
+                );
+
+                let response = ui.put(screen_rect, widget);
+                self.image_rects.insert(image_id, canvas_rect);
+
+                if response.dragged() && self.can_edit() {
+                    let moved = position + response.drag_delta() / self.transform.scaling;
+                    let kind = OperationKind::Move {
+                        position: [moved.x, moved.y],
+                    };
+                    // Peers only get the final position
+                    self.board.local(self.actor, image_id, kind);
+                }
+                if response.drag_stopped() {
+                    let kind = OperationKind::Move {
+                        position: [position.x, position.y],
+                    };
+                    self.board_operation(image_id, kind);
+                }
+                if response.clicked() {
+                    self.selected_image = Some(image_id);
+                }
+                self.image_context_menu(&response, image_id);
+            }
+
+            self.ui_text_items(ui, rect, window_layer);
+            self.ui_swatch_items(ui, rect, window_layer);
+            self.ui_annotation_layer(ctx, rect, window_layer);
+            self.paint_search_highlights(ctx, rect);
+
+            if let Some(hit) = self.search.zoom_to.take() {
+                if let Some(target) = self.hit_rect(hit) {
+                    self.zoom_to_rect(rect, target);
+                }
+            }
+        });
+
+        self.ui_invite_window(ctx);
+        self.ui_frame_export(ctx);
+        self.handle_palette_jobs();
+        self.ui_comment_thread(ctx);
+        self.ui_file_drag_and_drop(ctx);
+        self.handle_shortcuts(ctx);
+        self.commit_item_edits(ctx);
+        self.handle_p2p_messages(ctx);
+        self.update_roles(ctx);
+        self.update_catch_up(ctx);
+        self.paint_presences(ctx);
+        self.flush_outbox();
+        self.send_presence(ctx);
+    }
+}
+
+/// Uri the image bytes are registered under with the egui loaders.
+fn image_uri(image_id: ItemId) -> String {
+    format!("bytes://image_{image_id}")
+}
+
+// Rooms are announced every few seconds
+const DISCOVERED_ROOM_TIMEOUT: f64 = 15.0;
+
+// Rosters and join requests are repeated, so peers that join later get them
+const ROLES_INTERVAL: f64 = 5.0;
+const JOIN_REQUEST_TIMEOUT: f64 = 20.0;
+
+// Catch-up requests are repeated a few times, peers other than the host answer after a delay
+const CATCH_UP_RETRY: f64 = 5.0;
+const CATCH_UP_ATTEMPTS: u32 = 3;
+const CATCH_UP_REPLY_DELAY: std::ops::Range<f64> = 0.5..2.0;
+const CATCH_UP_IMAGE_WAIT: f64 = 60.0;
+
+// Presence is sent at most 20 times a second, and at least every few seconds
+const PRESENCE_INTERVAL: f64 = 0.05;
+const PRESENCE_KEEPALIVE: f64 = 2.0;
+const PRESENCE_TIMEOUT: f64 = 6.0;
+
+const PIN_RADIUS: f32 = 9.0;
+
+/// Keeps an overlay above the canvas items, but below windows, menus and popups.
+fn raise_overlay(ctx: &egui::Context, canvas_layer: egui::LayerId, layer: egui::LayerId) {
+    ctx.set_sublayer(canvas_layer, layer);
+    ctx.move_to_top(layer);
+}
+
+fn paint_comment_pin(painter: &egui::Painter, center: egui::Pos2, pin: &CommentPin) {
+    let fill = if pin.resolved {
+        egui::Color32::GRAY
+    } else {
+        egui::Color32::from_rgb(255, 170, 0)
+    };
+    painter.circle(
+        center,
+        PIN_RADIUS,
+        fill,
+        egui::Stroke::new(1.5, egui::Color32::BLACK),
+    );
+    painter.text(
+        center,
+        egui::Align2::CENTER_CENTER,
+        pin.comments.len().to_string(),
+        egui::FontId::proportional(11.0),
+        egui::Color32::BLACK,
+    );
+}
+
+/// Peer IDs are long, the tail is enough to tell peers apart.
+fn short_peer_id(peer_id: &str) -> &str {
+    &peer_id[peer_id.len().saturating_sub(6)..]
+}
+
+fn ui_qr_code(ui: &mut egui::Ui, text: &str) {
+    let code = match qrcode::QrCode::new(text) {
+        std::result::Result::Ok(code) => code,
+        Err(err) => {
+            ui.label(format!("No QR code: {err}"));
+            return;
+        }
+    };
+
+    const MODULE: f32 = 4.0;
+    const QUIET_ZONE: usize = 4; // Modules of blank border scanners need
+    let width = code.width();
+    let side = (width + 2 * QUIET_ZONE) as f32 * MODULE;
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(side, side), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::WHITE);
+    for (index, color) in code.to_colors().into_iter().enumerate() {
+        if color != qrcode::Color::Dark {
+            continue;
+        }
+        let (x, y) = (index % width + QUIET_ZONE, index / width + QUIET_ZONE);
+        let min = rect.min + egui::vec2(x as f32, y as f32) * MODULE;
+        painter.rect_filled(
+            egui::Rect::from_min_size(min, egui::vec2(MODULE, MODULE)),
+            0.0,
+            egui::Color32::BLACK,
+        );
+    }
+}
+
+fn presence_name<'a>(peer_id: &'a str, presence: &'a Presence) -> &'a str {
+    if presence.name.is_empty() {
+        short_peer_id(peer_id)
+    } else {
+        &presence.name
+    }
+}
+
+fn format_age(now_ms: u64, timestamp_ms: u64) -> String {
+    let minutes = now_ms.saturating_sub(timestamp_ms) / 60_000;
+    match minutes {
+        0 => "just now".to_owned(),
+        1..=59 => format!("{minutes} min ago"),
+        60..=1439 => format!("{} h ago", minutes / 60),
+        _ => format!("{} d ago", minutes / 1440),
+    }
+}
+
+fn ui_shape_menu(ui: &mut egui::Ui, shape: &mut ShapeItem) {
+    ui.horizontal(|ui| {
+        for (kind, label) in [
+            (ShapeKind::Arrow, "Arrow"),
+            (ShapeKind::Line, "Line"),
+            (ShapeKind::Rectangle, "Rectangle"),
+            (ShapeKind::Ellipse, "Ellipse"),
+        ] {
+            ui.selectable_value(&mut shape.kind, kind, label);
+        }
+    });
+    ui.add(egui::Slider::new(&mut shape.width, 1.0..=32.0).text("Width"));
+    ui.horizontal(|ui| {
+        ui.label("Colour");
+        color_edit_rgba(ui, &mut shape.color);
+    });
+}
+
+fn color_edit_rgba(ui: &mut egui::Ui, rgba: &mut [u8; 4]) -> egui::Response {
+    let [r, g, b, a] = *rgba;
+    let mut color = egui::Color32::from_rgba_unmultiplied(r, g, b, a);
+    let response = ui.color_edit_button_srgba(&mut color);
+    *rgba = color.to_srgba_unmultiplied();
+    response
+}
+
+/// Frame durations of an animated image. Known once the gif loader has decoded it.
+fn animation_durations(
+    ctx: &egui::Context,
+    uri: &str,
+) -> Option<std::sync::Arc<Vec<std::time::Duration>>> {
+    ctx.data(|data| data.get_temp::<egui::GifFrameDurations>(egui::Id::new(uri)))
+        .map(|durations| durations.0)
+        .filter(|durations| !durations.is_empty())
+}
+
+/// Lower case name of the image format, e.g. "png".
+fn image_format(bytes: &[u8]) -> Option<String> {
+    image::guess_format(bytes)
+        .ok()
+        .map(|format| format!("{format:?}").to_lowercase())
+}
+
+const DIMMED_OPACITY: f32 = 0.25;
+
+/// Fades out items that do not match the search.
+fn search_dimmed(widget: impl Widget, matched: bool) -> impl Widget {
+    move |ui: &mut egui::Ui| {
+        if !matched {
+            ui.multiply_opacity(DIMMED_OPACITY);
+        }
+        ui.add(widget)
+    }
+}
+
+/// Tag chips and a description field. New tags are typed into `tag_draft`.
+/// Returns whether the edit is finished: a tag was added or removed, or the description lost focus.
+fn ui_labels_editor(ui: &mut egui::Ui, labels: &mut ItemLabels, tag_draft: &mut String) -> bool {
+    let mut finished = false;
+    ui.label("Tags");
+    ui.horizontal_wrapped(|ui| {
+        let mut removed = None;
+        for (index, tag) in labels.tags.iter().enumerate() {
+            if ui
+                .small_button(format!("{tag} ×"))
+                .on_hover_text("Remove tag")
+                .clicked()
+            {
+                removed = Some(index);
+            }
+        }
+        if let Some(index) = removed {
+            labels.tags.remove(index);
+            finished = true;
+        }
+
+        let response = ui.add(
+            egui::TextEdit::singleline(tag_draft)
+                .hint_text("Add tag")
+                .desired_width(80.0),
+        );
+        if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+            let tag = tag_draft.trim();
+            if !tag.is_empty() && !labels.tags.iter().any(|existing| existing == tag) {
+                labels.tags.push(tag.to_owned());
+                finished = true;
+            }
+            tag_draft.clear();
+            response.request_focus();
+        }
+    });
+
+    ui.label("Description");
+    let description = ui.add(
+        egui::TextEdit::multiline(&mut labels.description)
+            .desired_rows(2)
+            .desired_width(200.0),
+    );
+    finished || description.lost_focus()
 }
 
 pub fn read_file_bytes(file_path: &PathBuf) -> Result<Vec<u8>> {
@@ -0,0 +1,137 @@
+use libp2p::{request_response, PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
+
+use super::{codec::BincodeCodec, crypto::Payload};
+use crate::metadata::content_hash;
+
+pub const BLOB_PROTOCOL: StreamProtocol = StreamProtocol::new("/muse/blob/1");
+
+pub type BlobCodec = BincodeCodec<BlobRequest, BlobResponse>;
+
+/// Image bytes keyed by their SHA-256, shared between the GUI and the p2p thread.
+/// Board items only refer to the hash, so identical images are stored once.
+#[derive(Clone, Default)]
+pub struct BlobStore {
+    blobs: Arc<RwLock<HashMap<String, Arc<[u8]>>>>,
+}
+
+impl BlobStore {
+    /// Stores the bytes and returns their hash.
+    pub fn insert(&self, bytes: Vec<u8>) -> String {
+        let hash = content_hash(&bytes);
+        self.blobs
+            .write()
+            .unwrap()
+            .insert(hash.clone(), bytes.into());
+        hash
+    }
+
+    /// Stores bytes received from elsewhere, if they match the expected hash.
+    pub fn insert_verified(&self, hash: &str, bytes: Vec<u8>) -> bool {
+        if content_hash(&bytes) != hash {
+            return false;
+        }
+        self.blobs
+            .write()
+            .unwrap()
+            .insert(hash.to_owned(), bytes.into());
+        true
+    }
+
+    pub fn get(&self, hash: &str) -> Option<Arc<[u8]>> {
+        self.blobs.read().unwrap().get(hash).cloned()
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.blobs.read().unwrap().contains_key(hash)
+    }
+
+    /// Copies of the blobs with the given hashes, e.g. for saving a board.
+    pub fn subset<'a>(
+        &self,
+        hashes: impl IntoIterator<Item = &'a str>,
+    ) -> HashMap<String, Vec<u8>> {
+        let blobs = self.blobs.read().unwrap();
+        hashes
+            .into_iter()
+            .filter_map(|hash| Some((hash.to_owned(), blobs.get(hash)?.to_vec())))
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BlobRequest {
+    pub hash: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BlobResponse {
+    pub bytes: Option<Payload>, // None if the peer does not have the blob
+}
+
+/// Blobs this peer is missing, and the peers they can still be asked for.
+/// Peers are tried one at a time until one of them sends bytes that match the hash.
+#[derive(Default)]
+pub struct BlobFetcher {
+    pending: HashMap<String, Vec<PeerId>>,
+    tried: HashMap<String, HashSet<PeerId>>,
+    in_flight: HashMap<request_response::OutboundRequestId, String>,
+}
+
+impl BlobFetcher {
+    /// Adds peers to ask for a blob. Returns the peer to ask now, if no request is running.
+    pub fn want(&mut self, hash: &str, peers: impl IntoIterator<Item = PeerId>) -> Option<PeerId> {
+        let tried = self.tried.entry(hash.to_owned()).or_default();
+        let candidates = self.pending.entry(hash.to_owned()).or_default();
+        for peer in peers {
+            if !tried.contains(&peer) && !candidates.contains(&peer) {
+                candidates.push(peer);
+            }
+        }
+
+        if self.in_flight.values().any(|pending| pending == hash) {
+            return None;
+        }
+        self.next_peer(hash)
+    }
+
+    /// Records the request sent to the peer returned by `want` or `failed`.
+    pub fn started(&mut self, request: request_response::OutboundRequestId, hash: &str) {
+        self.in_flight.insert(request, hash.to_owned());
+    }
+
+    /// Takes the hash a response or failure belongs to.
+    pub fn finished(&mut self, request: request_response::OutboundRequestId) -> Option<String> {
+        self.in_flight.remove(&request)
+    }
+
+    pub fn succeeded(&mut self, hash: &str) {
+        self.pending.remove(hash);
+        self.tried.remove(hash);
+    }
+
+    /// Returns the next peer to ask, or `None` if every candidate has been tried.
+    pub fn failed(&mut self, hash: &str) -> Option<PeerId> {
+        let next = self.next_peer(hash);
+        if next.is_none() {
+            // A later announcement starts over with fresh candidates
+            self.pending.remove(hash);
+            self.tried.remove(hash);
+        }
+        next
+    }
+
+    fn next_peer(&mut self, hash: &str) -> Option<PeerId> {
+        let candidates = self.pending.get_mut(hash)?;
+        if candidates.is_empty() {
+            return None;
+        }
+        let peer = candidates.remove(0);
+        self.tried.entry(hash.to_owned()).or_default().insert(peer);
+        Some(peer)
+    }
+}
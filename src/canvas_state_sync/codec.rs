@@ -0,0 +1,101 @@
+use async_trait::async_trait;
+use futures::prelude::*;
+use libp2p::{request_response, StreamProtocol};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{io, marker::PhantomData};
+
+use super::sync_types::decode_limited;
+
+const MAX_REQUEST_SIZE: u64 = 64 * 1024;
+// Images are fetched in one response, so this is also the largest image that can be shared
+const MAX_RESPONSE_SIZE: u64 = 64 * 1024 * 1024 + MAX_REQUEST_SIZE;
+
+/// Bincode codec for request-response protocols. Both sides close the stream after writing.
+pub struct BincodeCodec<Req, Resp> {
+    phantom: PhantomData<(Req, Resp)>,
+}
+
+impl<Req, Resp> Default for BincodeCodec<Req, Resp> {
+    fn default() -> Self {
+        Self {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<Req, Resp> Clone for BincodeCodec<Req, Resp> {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl<Req, Resp> request_response::Codec for BincodeCodec<Req, Resp>
+where
+    Req: Send + Serialize + DeserializeOwned,
+    Resp: Send + Serialize + DeserializeOwned,
+{
+    type Protocol = StreamProtocol;
+    type Request = Req;
+    type Response = Resp;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Req>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_bincode(io, MAX_REQUEST_SIZE).await
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Resp>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_bincode(io, MAX_RESPONSE_SIZE).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        request: Req,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_bytes(io, encode(&request)?).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        response: Resp,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_bytes(io, encode(&response)?).await
+    }
+}
+
+async fn read_bincode<T, M>(io: &mut T, limit: u64) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: DeserializeOwned,
+{
+    let mut bytes = Vec::new();
+    io.take(limit).read_to_end(&mut bytes).await?;
+    decode_limited(&bytes, limit as usize).map_err(io::Error::other)
+}
+
+fn encode<M: Serialize>(message: &M) -> io::Result<Vec<u8>> {
+    bincode::serialize(message).map_err(io::Error::other)
+}
+
+async fn write_bytes<T>(io: &mut T, bytes: Vec<u8>) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+{
+    io.write_all(&bytes).await?;
+    io.close().await
+}
@@ -0,0 +1,619 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::board::{Comment, ImageInfo, InkStroke, ItemId, ShapeItem, SwatchItem, TextItem};
+
+pub type ActorId = u64;
+
+/// Lamport timestamp of an operation. Ties are broken by actor, so stamps are totally ordered.
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Debug,
+)]
+pub struct Stamp {
+    pub counter: u64,
+    pub actor: ActorId,
+}
+
+/// Last writer wins register.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct Lww<T> {
+    pub value: T,
+    pub stamp: Stamp,
+}
+
+impl<T: Clone> Lww<T> {
+    fn set(&mut self, value: T, stamp: Stamp) {
+        if stamp > self.stamp {
+            self.value = value;
+            self.stamp = stamp;
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.set(other.value.clone(), other.stamp);
+    }
+}
+
+/// Fractional z-index. Digits of a base 256 fraction, compared lexicographically.
+/// There is always room for another index between two others, so reordering
+/// an item never renumbers any other item.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct ZIndex(pub Vec<u8>);
+
+impl ZIndex {
+    /// Index strictly between `low` and `high`. `None` stands for the bottom or the top.
+    /// Generated indices never end in a zero digit, which keeps them comparable as fractions.
+    pub fn between(low: Option<&ZIndex>, high: Option<&ZIndex>) -> ZIndex {
+        // Concurrent reorders can leave neighbours on the same index. Then there is no room
+        // in between, and going just above `low` is the best there is.
+        let high = high.filter(|high| low.is_none_or(|low| low < *high));
+        let low = low.map_or(&[][..], |low| &low.0[..]);
+        let mut high = high.map(|high| &high.0[..]);
+        let mut digits = vec![];
+
+        for position in 0.. {
+            let low_digit = low.get(position).copied().unwrap_or(0) as u16;
+            let high_digit = match high {
+                Some(high) => high.get(position).copied().unwrap_or(0) as u16,
+                None => 256,
+            };
+
+            if high_digit > low_digit + 1 {
+                digits.push(((low_digit + high_digit) / 2) as u8);
+                break;
+            }
+
+            digits.push(low_digit as u8);
+            if high_digit == low_digit + 1 {
+                // Anything after this digit stays below `high`
+                high = None;
+            }
+        }
+
+        ZIndex(digits)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct ImageTransform {
+    pub scale: f32,
+}
+
+impl Default for ImageTransform {
+    fn default() -> Self {
+        Self { scale: 1.0 }
+    }
+}
+
+/// An image on the board. Every field converges on its own.
+/// The bytes are not part of the board, they are looked up by `info.content_hash`.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct BoardImage {
+    pub added: bool, // Operations can arrive before the image is added
+    pub info: Lww<ImageInfo>,
+    pub position: Lww<[f32; 2]>, // Top left corner, in canvas space
+    pub transform: Lww<ImageTransform>,
+    pub z: Lww<ZIndex>,
+    pub deleted: bool, // Deletes win over concurrent edits, and are final
+}
+
+impl BoardImage {
+    pub fn is_visible(&self) -> bool {
+        self.added && !self.deleted
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.added |= other.added;
+        self.info.merge(&other.info);
+        self.position.merge(&other.position);
+        self.transform.merge(&other.transform);
+        self.z.merge(&other.z);
+        self.deleted |= other.deleted;
+    }
+}
+
+/// Any other item. Edits replace the whole item, and removing one is just another edit,
+/// so undoing a removal brings the item back.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum Item {
+    Text(TextItem),
+    Stroke(InkStroke),
+    Shape(ShapeItem),
+    Swatch(SwatchItem),
+}
+
+/// A comment thread. Replies from different peers are all kept.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct BoardPin {
+    pub added: bool,
+    pub image: Lww<ItemId>,
+    pub anchor: Lww<[f32; 2]>, // Relative to the image size
+    pub resolved: Lww<bool>,
+    pub comments: HashMap<ItemId, Lww<Comment>>,
+    pub deleted: bool,
+}
+
+impl BoardPin {
+    pub fn is_visible(&self) -> bool {
+        self.added && !self.deleted
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.added |= other.added;
+        self.image.merge(&other.image);
+        self.anchor.merge(&other.anchor);
+        self.resolved.merge(&other.resolved);
+        for (id, comment) in &other.comments {
+            self.comments.entry(*id).or_default().merge(comment);
+        }
+        self.deleted |= other.deleted;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum OperationKind {
+    Add {
+        info: ImageInfo,
+        position: [f32; 2],
+        z: ZIndex,
+    },
+    Move {
+        position: [f32; 2],
+    },
+    Transform {
+        transform: ImageTransform,
+    },
+    Reorder {
+        z: ZIndex,
+    },
+    Describe {
+        info: ImageInfo,
+    },
+    Delete,
+    // Texts, strokes, shapes and swatches
+    Put {
+        item: Item,
+    },
+    Remove,
+    // Comment pins
+    AddPin {
+        image: ItemId,
+        anchor: [f32; 2],
+    },
+    Reply {
+        comment: Comment,
+    },
+    Resolve {
+        resolved: bool,
+    },
+    DeletePin,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Operation {
+    pub stamp: Stamp,
+    pub item: ItemId,
+    pub kind: OperationKind,
+}
+
+/// Replicated board state. Operations are commutative and idempotent,
+/// so every peer ends up with the same board regardless of delivery order or duplicates.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct Board {
+    pub images: HashMap<ItemId, BoardImage>,
+    pub items: HashMap<ItemId, Lww<Option<Item>>>,
+    pub pins: HashMap<ItemId, BoardPin>,
+    pub clock: u64, // Highest counter seen
+}
+
+impl Board {
+    /// Creates a local operation and applies it.
+    pub fn local(&mut self, actor: ActorId, item: ItemId, kind: OperationKind) -> Operation {
+        let operation = Operation {
+            stamp: self.tick(actor),
+            item,
+            kind,
+        };
+        self.apply(&operation);
+        operation
+    }
+
+    /// Stamp for a local change, later than everything seen so far.
+    /// Also used for state kept outside the board, like animation playback.
+    pub fn tick(&mut self, actor: ActorId) -> Stamp {
+        self.clock += 1;
+        Stamp {
+            counter: self.clock,
+            actor,
+        }
+    }
+
+    /// Records a stamp seen outside of an operation, so local stamps come after it.
+    pub fn witness(&mut self, stamp: Stamp) {
+        self.clock = self.clock.max(stamp.counter);
+    }
+
+    pub fn apply(&mut self, operation: &Operation) {
+        self.clock = self.clock.max(operation.stamp.counter);
+        let stamp = operation.stamp;
+        let id = operation.item;
+
+        match &operation.kind {
+            OperationKind::Add { info, position, z } => {
+                let image = self.image_mut(id);
+                image.added = true;
+                image.info.set(info.clone(), stamp);
+                image.position.set(*position, stamp);
+                image.transform.set(ImageTransform::default(), stamp);
+                image.z.set(z.clone(), stamp);
+            }
+            OperationKind::Move { position } => self.image_mut(id).position.set(*position, stamp),
+            OperationKind::Transform { transform } => {
+                self.image_mut(id).transform.set(*transform, stamp)
+            }
+            OperationKind::Reorder { z } => self.image_mut(id).z.set(z.clone(), stamp),
+            OperationKind::Describe { info } => self.image_mut(id).info.set(info.clone(), stamp),
+            OperationKind::Delete => self.image_mut(id).deleted = true,
+            OperationKind::Put { item } => self.item_mut(id).set(Some(item.clone()), stamp),
+            OperationKind::Remove => self.item_mut(id).set(None, stamp),
+            OperationKind::AddPin { image, anchor } => {
+                let pin = self.pin_mut(id);
+                pin.added = true;
+                pin.image.set(*image, stamp);
+                pin.anchor.set(*anchor, stamp);
+            }
+            OperationKind::Reply { comment } => {
+                let comments = &mut self.pin_mut(id).comments;
+                comments
+                    .entry(comment.id)
+                    .or_default()
+                    .set(comment.clone(), stamp);
+            }
+            OperationKind::Resolve { resolved } => self.pin_mut(id).resolved.set(*resolved, stamp),
+            OperationKind::DeletePin => self.pin_mut(id).deleted = true,
+        }
+    }
+
+    // Operations can arrive before the item is added, so entries are created on demand
+    fn image_mut(&mut self, id: ItemId) -> &mut BoardImage {
+        self.images.entry(id).or_default()
+    }
+
+    fn item_mut(&mut self, id: ItemId) -> &mut Lww<Option<Item>> {
+        self.items.entry(id).or_default()
+    }
+
+    fn pin_mut(&mut self, id: ItemId) -> &mut BoardPin {
+        self.pins.entry(id).or_default()
+    }
+
+    /// Merges a whole board, e.g. a snapshot from another peer.
+    pub fn merge(&mut self, other: &Board) {
+        self.clock = self.clock.max(other.clock);
+        for (id, image) in &other.images {
+            self.images.entry(*id).or_default().merge(image);
+        }
+        for (id, item) in &other.items {
+            self.items.entry(*id).or_default().merge(item);
+        }
+        for (id, pin) in &other.pins {
+            self.pins.entry(*id).or_default().merge(pin);
+        }
+    }
+
+    pub fn item(&self, id: ItemId) -> Option<&Item> {
+        self.items.get(&id)?.value.as_ref()
+    }
+
+    /// Texts, strokes, shapes and swatches, oldest edit first.
+    pub fn items(&self) -> Vec<(ItemId, &Item)> {
+        let mut items: Vec<_> = self
+            .items
+            .iter()
+            .filter_map(|(id, item)| Some((item.stamp, *id, item.value.as_ref()?)))
+            .collect();
+        items.sort_by_key(|(stamp, id, _)| (*stamp, *id));
+        items.into_iter().map(|(_, id, item)| (id, item)).collect()
+    }
+
+    /// Visible comment pins, by ID.
+    pub fn pins(&self) -> Vec<(ItemId, &BoardPin)> {
+        let mut pins: Vec<_> = self
+            .pins
+            .iter()
+            .filter(|(_, pin)| pin.is_visible())
+            .map(|(id, pin)| (*id, pin))
+            .collect();
+        pins.sort_by_key(|(id, _)| *id);
+        pins
+    }
+
+    pub fn image(&self, id: ItemId) -> Option<&BoardImage> {
+        self.images.get(&id).filter(|image| image.is_visible())
+    }
+
+    /// Visible images, bottom to top.
+    pub fn ordered(&self) -> Vec<(ItemId, &BoardImage)> {
+        let mut images: Vec<_> = self
+            .images
+            .iter()
+            .filter(|(_, image)| image.is_visible())
+            .map(|(id, image)| (*id, image))
+            .collect();
+        // Concurrent reorders can produce equal indices, the item ID settles those
+        images.sort_by(|(a_id, a), (b_id, b)| (&a.z.value, a_id).cmp(&(&b.z.value, b_id)));
+        images
+    }
+
+    /// Index above every visible image.
+    pub fn top_z(&self) -> ZIndex {
+        ZIndex::between(self.ordered().last().map(|(_, image)| &image.z.value), None)
+    }
+
+    /// Index for moving `item` one step up or down, or to the very top or bottom.
+    pub fn reorder_z(&self, item: ItemId, step: Reorder) -> Option<ZIndex> {
+        let ordered = self.ordered();
+        let index = ordered.iter().position(|(id, _)| *id == item)?;
+        let z = |index: usize| ordered.get(index).map(|(_, image)| &image.z.value);
+
+        let (low, high) = match step {
+            Reorder::ToFront if index + 1 < ordered.len() => (z(ordered.len() - 1), None),
+            Reorder::Forward if index + 1 < ordered.len() => (z(index + 1), z(index + 2)),
+            Reorder::Backward if index > 0 => (index.checked_sub(2).and_then(z), z(index - 1)),
+            Reorder::ToBack if index > 0 => (None, z(0)),
+            _ => return None,
+        };
+        Some(ZIndex::between(low, high))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Reorder {
+    ToFront,
+    Forward,
+    Backward,
+    ToBack,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{ShapeAnchor, ShapeKind};
+    use proptest::prelude::*;
+
+    fn kind_strategy() -> impl Strategy<Value = OperationKind> {
+        let z = proptest::collection::vec(1u8.., 1..4).prop_map(ZIndex);
+        let position = (-100.0f32..100.0, -100.0f32..100.0).prop_map(|(x, y)| [x, y]);
+        prop_oneof![
+            (".{0,4}", position.clone(), z.clone()).prop_map(|(content_hash, position, z)| {
+                OperationKind::Add {
+                    info: ImageInfo {
+                        content_hash,
+                        ..Default::default()
+                    },
+                    position,
+                    z,
+                }
+            }),
+            position
+                .clone()
+                .prop_map(|position| OperationKind::Move { position }),
+            (0.1f32..10.0).prop_map(|scale| OperationKind::Transform {
+                transform: ImageTransform { scale }
+            }),
+            z.prop_map(|z| OperationKind::Reorder { z }),
+            ".{0,4}".prop_map(|source_url| OperationKind::Describe {
+                info: ImageInfo {
+                    source_url,
+                    ..Default::default()
+                }
+            }),
+            Just(OperationKind::Delete),
+            item_strategy().prop_map(|item| OperationKind::Put { item }),
+            Just(OperationKind::Remove),
+            (0u64..4, position).prop_map(|(image, anchor)| OperationKind::AddPin { image, anchor }),
+            (0u64..4, ".{0,4}").prop_map(|(id, text)| OperationKind::Reply {
+                comment: Comment {
+                    id,
+                    text,
+                    ..Default::default()
+                }
+            }),
+            any::<bool>().prop_map(|resolved| OperationKind::Resolve { resolved }),
+            Just(OperationKind::DeletePin),
+        ]
+    }
+
+    fn item_strategy() -> impl Strategy<Value = Item> {
+        let position = (-100.0f32..100.0, -100.0f32..100.0).prop_map(|(x, y)| [x, y]);
+        prop_oneof![
+            (".{0,4}", position.clone()).prop_map(|(text, position)| {
+                Item::Text(TextItem {
+                    text,
+                    ..TextItem::new(position)
+                })
+            }),
+            proptest::collection::vec(position.clone(), 1..4).prop_map(|points| {
+                Item::Stroke(InkStroke {
+                    id: 0,
+                    points,
+                    width: 2.0,
+                    color: [0, 0, 0, 255],
+                    highlighter: false,
+                    attached_to: None,
+                })
+            }),
+            (position.clone(), position.clone()).prop_map(|(start, end)| {
+                Item::Shape(ShapeItem {
+                    id: 0,
+                    kind: ShapeKind::Arrow,
+                    start: ShapeAnchor::Point(start),
+                    end: ShapeAnchor::Point(end),
+                    width: 2.0,
+                    color: [0, 0, 0, 255],
+                })
+            }),
+            position.prop_map(|position| {
+                Item::Swatch(SwatchItem {
+                    id: 0,
+                    position,
+                    colors: vec![[0, 0, 0]],
+                    labels: Default::default(),
+                })
+            }),
+        ]
+    }
+
+    /// Operations as created by a few actors editing a few items.
+    /// Counters are unique per actor, like the ones `Board::local` hands out.
+    fn operations_strategy() -> impl Strategy<Value = Vec<Operation>> {
+        proptest::collection::vec((0u64..3, 0u64..4, kind_strategy()), 0..40).prop_map(
+            |operations| {
+                operations
+                    .into_iter()
+                    .enumerate()
+                    .map(|(counter, (actor, item, kind))| Operation {
+                        stamp: Stamp {
+                            counter: counter as u64 + 1,
+                            actor,
+                        },
+                        item,
+                        kind,
+                    })
+                    .collect()
+            },
+        )
+    }
+
+    fn board_from(operations: &[Operation]) -> Board {
+        let mut board = Board::default();
+        for operation in operations {
+            board.apply(operation);
+        }
+        board
+    }
+
+    proptest! {
+        #[test]
+        fn delivery_order_does_not_matter(
+            (operations, shuffled) in operations_strategy().prop_flat_map(|operations| {
+                let shuffled = Just(operations.clone()).prop_shuffle();
+                (Just(operations), shuffled)
+            })
+        ) {
+            prop_assert_eq!(board_from(&operations), board_from(&shuffled));
+        }
+
+        #[test]
+        fn duplicates_do_not_matter(operations in operations_strategy(), repeat in 0usize..40) {
+            let mut duplicated = operations.clone();
+            duplicated.extend(operations.iter().take(repeat).cloned());
+            prop_assert_eq!(board_from(&operations), board_from(&duplicated));
+        }
+
+        #[test]
+        fn merging_snapshots_converges(operations in operations_strategy(), split in any::<prop::sample::Index>()) {
+            let split = split.index(operations.len() + 1);
+            let (first, second) = operations.split_at(split);
+
+            let mut a = board_from(first);
+            a.merge(&board_from(second));
+            let mut b = board_from(second);
+            b.merge(&board_from(first));
+
+            let expected = board_from(&operations);
+            prop_assert_eq!(&a, &expected);
+            prop_assert_eq!(&b, &expected);
+
+            // Merging is idempotent
+            a.merge(&expected);
+            prop_assert_eq!(a, expected);
+        }
+
+        #[test]
+        fn peers_editing_concurrently_converge(
+            edits in proptest::collection::vec((0usize..3, 0u64..4, kind_strategy()), 0..40),
+            deliveries in proptest::collection::vec(any::<prop::sample::Index>(), 0..40),
+        ) {
+            // Each peer creates local operations, and receives the others' in random order
+            let mut peers = vec![Board::default(); 3];
+            let mut sent: Vec<Operation> = vec![];
+
+            for (step, (peer, item, kind)) in edits.into_iter().enumerate() {
+                sent.push(peers[peer].local(peer as ActorId, item, kind));
+                if let Some(index) = deliveries.get(step) {
+                    let operation = &sent[index.index(sent.len())];
+                    peers[(peer + 1) % 3].apply(operation);
+                }
+            }
+            for peer in &mut peers {
+                for operation in sent.iter().rev() {
+                    peer.apply(operation);
+                }
+            }
+
+            prop_assert_eq!(&peers[0], &peers[1]);
+            prop_assert_eq!(&peers[1], &peers[2]);
+        }
+
+        #[test]
+        fn z_between_is_strictly_between(
+            a in proptest::collection::vec(any::<u8>(), 0..5),
+            b in proptest::collection::vec(any::<u8>(), 0..5),
+        ) {
+            // Valid indices never end in zero
+            let a = ZIndex(a).normalized();
+            let b = ZIndex(b).normalized();
+            prop_assume!(a != b);
+            let (low, high) = if a < b { (a, b) } else { (b, a) };
+
+            let low = (!low.0.is_empty()).then_some(low);
+            let middle = ZIndex::between(low.as_ref(), Some(&high));
+            if let Some(low) = &low {
+                prop_assert!(low < &middle);
+            }
+            prop_assert!(middle < high);
+            prop_assert_ne!(middle.0.last(), Some(&0));
+        }
+    }
+
+    impl ZIndex {
+        fn normalized(mut self) -> Self {
+            while self.0.last() == Some(&0) {
+                self.0.pop();
+            }
+            self
+        }
+    }
+
+    #[test]
+    fn reordering_moves_items() {
+        let mut board = Board::default();
+        for item in 0..3 {
+            let z = board.top_z();
+            board.local(
+                0,
+                item,
+                OperationKind::Add {
+                    info: ImageInfo::default(),
+                    position: [0.0, 0.0],
+                    z,
+                },
+            );
+        }
+        let order =
+            |board: &Board| -> Vec<ItemId> { board.ordered().iter().map(|(id, _)| *id).collect() };
+        assert_eq!(order(&board), [0, 1, 2]);
+
+        for (item, step, expected) in [
+            (0, Reorder::ToFront, [1, 2, 0]),
+            (0, Reorder::Backward, [1, 0, 2]),
+            (2, Reorder::ToBack, [2, 1, 0]),
+            (2, Reorder::Forward, [1, 2, 0]),
+        ] {
+            let z = board.reorder_z(item, step).unwrap();
+            board.local(0, item, OperationKind::Reorder { z });
+            assert_eq!(order(&board), expected);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::{anyhow, Result};
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+
+/// Key of an encrypted room, derived from its passphrase.
+pub struct RoomKey {
+    cipher: XChaCha20Poly1305,
+}
+
+impl RoomKey {
+    /// Argon2id over the passphrase. The room code is the salt, so every peer in the room
+    /// derives the same key, and the same passphrase gives another key in another room.
+    pub fn derive(passphrase: &str, room_code: &str) -> Result<Self> {
+        let salt = format!("muse-room-{room_code}");
+        let mut key = [0; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt.as_bytes(), &mut key)
+            .map_err(|err| anyhow!("Key derivation failed: {err}"))?;
+        Ok(Self {
+            cipher: XChaCha20Poly1305::new(&key.into()),
+        })
+    }
+}
+
+/// Data sent to peers, encrypted if the room has a key.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Payload {
+    Plain(Vec<u8>),
+    Sealed {
+        nonce: [u8; 24],
+        ciphertext: Vec<u8>,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OpenError {
+    PassphraseNeeded, // Encrypted, but no passphrase was entered
+    WrongPassphrase,  // Encrypted with another key, or tampered with
+    Unencrypted,      // Plain data in a room that has a key
+}
+
+impl std::fmt::Display for OpenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            OpenError::PassphraseNeeded => "The room is encrypted, enter its passphrase",
+            OpenError::WrongPassphrase => "Wrong passphrase, peer data could not be decrypted",
+            OpenError::Unencrypted => "A peer sent unencrypted data to the encrypted room",
+        })
+    }
+}
+
+pub fn seal(key: Option<&RoomKey>, plaintext: Vec<u8>) -> Payload {
+    let Some(key) = key else {
+        return Payload::Plain(plaintext);
+    };
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = key
+        .cipher
+        .encrypt(&nonce, plaintext.as_slice())
+        .expect("Encryption cannot fail for in-memory data");
+    Payload::Sealed {
+        nonce: nonce.into(),
+        ciphertext,
+    }
+}
+
+pub fn open(key: Option<&RoomKey>, payload: Payload) -> Result<Vec<u8>, OpenError> {
+    match (key, payload) {
+        (None, Payload::Plain(plaintext)) => Ok(plaintext),
+        (None, Payload::Sealed { .. }) => Err(OpenError::PassphraseNeeded),
+        (Some(_), Payload::Plain(_)) => Err(OpenError::Unencrypted),
+        (Some(key), Payload::Sealed { nonce, ciphertext }) => key
+            .cipher
+            .decrypt(XNonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| OpenError::WrongPassphrase),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(passphrase: &str) -> RoomKey {
+        RoomKey::derive(passphrase, "ABCD-1234").unwrap()
+    }
+
+    #[test]
+    fn sealed_payloads_round_trip() {
+        let key = key("correct horse");
+        let payload = seal(Some(&key), b"board".to_vec());
+        assert!(matches!(payload, Payload::Sealed { .. }));
+        assert_eq!(open(Some(&key), payload).unwrap(), b"board");
+
+        let plain = seal(None, b"board".to_vec());
+        assert_eq!(open(None, plain).unwrap(), b"board");
+    }
+
+    #[test]
+    fn wrong_passphrase_or_room_is_rejected() {
+        let payload = seal(Some(&key("correct horse")), b"board".to_vec());
+        assert_eq!(
+            open(Some(&key("battery staple")), payload),
+            Err(OpenError::WrongPassphrase)
+        );
+
+        let other_room = RoomKey::derive("correct horse", "WXYZ-9876").unwrap();
+        let payload = seal(Some(&key("correct horse")), b"board".to_vec());
+        assert_eq!(
+            open(Some(&other_room), payload),
+            Err(OpenError::WrongPassphrase)
+        );
+    }
+
+    #[test]
+    fn plain_payloads_are_rejected_in_encrypted_rooms() {
+        let payload = seal(None, b"board".to_vec());
+        assert_eq!(
+            open(Some(&key("correct horse")), payload),
+            Err(OpenError::Unencrypted)
+        );
+    }
+
+    #[test]
+    fn sealed_payloads_need_a_passphrase() {
+        let payload = seal(Some(&key("correct horse")), b"board".to_vec());
+        assert_eq!(open(None, payload), Err(OpenError::PassphraseNeeded));
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let key = key("correct horse");
+        let Payload::Sealed { nonce, ciphertext } = seal(Some(&key), b"board".to_vec()) else {
+            panic!("Expected a sealed payload");
+        };
+
+        let mut tampered = ciphertext.clone();
+        tampered[0] ^= 1;
+        let payload = Payload::Sealed {
+            nonce,
+            ciphertext: tampered,
+        };
+        assert_eq!(open(Some(&key), payload), Err(OpenError::WrongPassphrase));
+
+        let mut tampered_nonce = nonce;
+        tampered_nonce[0] ^= 1;
+        let payload = Payload::Sealed {
+            nonce: tampered_nonce,
+            ciphertext: ciphertext.clone(),
+        };
+        assert_eq!(open(Some(&key), payload), Err(OpenError::WrongPassphrase));
+
+        let payload = Payload::Sealed {
+            nonce,
+            ciphertext: ciphertext[..ciphertext.len() - 1].to_vec(),
+        };
+        assert_eq!(open(Some(&key), payload), Err(OpenError::WrongPassphrase));
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::{anyhow, Result};
+use libp2p::{identity::Keypair, PeerId};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+const IDENTITY_FILE: &str = "identity.bin";
+
+/// Who this app is to its peers. Kept in the app data directory,
+/// so the peer ID stays the same between launches.
+#[derive(Clone)]
+pub struct Identity {
+    pub keypair: Keypair,
+    pub display_name: String,
+    pub color: [u8; 3],
+    // Why the saved identity could not be loaded. This one is then only temporary,
+    // and never saved over the file, which might still be recoverable.
+    pub load_error: Option<String>,
+}
+
+// On disk, and in exported identity files
+#[derive(Serialize, Deserialize)]
+struct IdentityFile {
+    keypair: Vec<u8>, // Protobuf encoding used by libp2p
+    display_name: String,
+    color: [u8; 3],
+}
+
+impl Default for Identity {
+    fn default() -> Self {
+        let hue = rand::random::<f32>();
+        let color = eframe::egui::ecolor::Hsva::new(hue, 0.6, 0.95, 1.0).to_srgb();
+        Self {
+            keypair: Keypair::generate_ed25519(),
+            display_name: String::new(),
+            color,
+            load_error: None,
+        }
+    }
+}
+
+impl Identity {
+    /// Loads the saved identity, or creates and saves a new one on first launch.
+    pub fn load_or_create() -> Self {
+        let path = identity_path();
+        if path.exists() {
+            match Self::import(&path) {
+                Ok(identity) => return identity,
+                Err(err) => {
+                    println!("Could not load identity from {}: {err:?}", path.display());
+                    return Self {
+                        load_error: Some(format!("Could not read {}: {err}", path.display())),
+                        ..Self::default()
+                    };
+                }
+            }
+        }
+
+        let identity = Self::default();
+        if let Err(err) = identity.save() {
+            println!("Could not save identity: {err:?}");
+        }
+        identity
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if self.load_error.is_some() {
+            return Err(anyhow!(
+                "Not overwriting the identity file that could not be read"
+            ));
+        }
+        let path = identity_path();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        self.export(&path)
+    }
+
+    /// Writes the identity, including the private key, to `path`.
+    pub fn export(&self, path: &Path) -> Result<()> {
+        let file = IdentityFile {
+            keypair: self.keypair.to_protobuf_encoding()?,
+            display_name: self.display_name.clone(),
+            color: self.color,
+        };
+        write_private(path, &bincode::serialize(&file)?)
+    }
+
+    pub fn import(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let file: IdentityFile = bincode::deserialize(&bytes)?;
+        let keypair = Keypair::from_protobuf_encoding(&file.keypair)
+            .map_err(|err| anyhow!("Invalid key: {err}"))?;
+        Ok(Self {
+            keypair,
+            display_name: file.display_name,
+            color: file.color,
+            load_error: None,
+        })
+    }
+
+    pub fn peer_id(&self) -> String {
+        self.keypair.public().to_peer_id().to_string()
+    }
+}
+
+/// Writes a file only the current user can read, since it holds the private key.
+fn write_private(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // The mode only applies to new files
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(bytes)?;
+    Ok(())
+}
+
+/// Short hash of a peer ID, for comparing identities out loud or side by side.
+pub fn fingerprint(peer_id: &str) -> String {
+    let bytes = match peer_id.parse::<PeerId>() {
+        Ok(peer_id) => peer_id.to_bytes(),
+        Err(_) => peer_id.as_bytes().to_vec(),
+    };
+    let hash = Sha256::digest(bytes);
+    hash[..8]
+        .chunks(2)
+        .map(|pair| format!("{:02X}{:02X}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// File in the per-user app data directory. Falls back to the working directory where there is none.
+pub fn data_path(file: &str) -> PathBuf {
+    dirs::data_dir()
+        .map(|dir| dir.join("muse"))
+        .unwrap_or_default()
+        .join(file)
+}
+
+fn identity_path() -> PathBuf {
+    data_path(IDENTITY_FILE)
+}
@@ -0,0 +1,172 @@
+use anyhow::{anyhow, Result};
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use super::identity::data_path;
+
+const KNOWN_PEERS_FILE: &str = "known_peers.json";
+
+/// Everything needed to dial a peer without mDNS: its ID and the addresses it listens on.
+/// Written as the addresses separated by spaces, each ending in `/p2p/<peer ID>`,
+/// so a single multiaddr copied from elsewhere works too.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Invite {
+    pub peer_id: PeerId,
+    pub addresses: Vec<Multiaddr>,
+}
+
+impl Invite {
+    pub fn new(peer_id: PeerId, addresses: Vec<Multiaddr>) -> Self {
+        let addresses = addresses
+            .into_iter()
+            .map(|mut address| {
+                if matches!(address.iter().last(), Some(Protocol::P2p(_))) {
+                    address.pop();
+                }
+                address
+            })
+            .collect();
+        Self { peer_id, addresses }
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut peer_id = None;
+        let mut addresses = Vec::new();
+        for part in text.split([' ', ',', '\n']).filter(|part| !part.is_empty()) {
+            let mut address: Multiaddr = part
+                .trim()
+                .parse()
+                .map_err(|err| anyhow!("{part} is not an address: {err}"))?;
+            let Some(Protocol::P2p(id)) = address.pop() else {
+                return Err(anyhow!("{part} does not end in a peer ID"));
+            };
+            if peer_id.is_some_and(|peer_id| peer_id != id) {
+                return Err(anyhow!("The addresses belong to different peers"));
+            }
+            peer_id = Some(id);
+            addresses.push(address);
+        }
+        let peer_id = peer_id.ok_or_else(|| anyhow!("The invite is empty"))?;
+        Ok(Self { peer_id, addresses })
+    }
+}
+
+impl std::fmt::Display for Invite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let addresses: Vec<String> = self
+            .addresses
+            .iter()
+            .map(|address| format!("{address}/p2p/{}", self.peer_id))
+            .collect();
+        f.write_str(&addresses.join(" "))
+    }
+}
+
+/// Peer connected to through an invite, dialed again whenever P2P starts.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KnownPeer {
+    pub invite: String,
+    pub name: Option<String>,
+}
+
+fn known_peers_path() -> PathBuf {
+    data_path(KNOWN_PEERS_FILE)
+}
+
+pub fn load_known_peers() -> Vec<KnownPeer> {
+    let Ok(bytes) = std::fs::read(known_peers_path()) else {
+        return Vec::new();
+    };
+    serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+        println!("Could not read known peers: {err}");
+        Vec::new()
+    })
+}
+
+pub fn save_known_peers(peers: &[KnownPeer]) -> Result<()> {
+    let path = known_peers_path();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_vec_pretty(peers)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity::Keypair;
+
+    fn peer() -> PeerId {
+        Keypair::generate_ed25519().public().to_peer_id()
+    }
+
+    fn address(text: &str) -> Multiaddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let invite = Invite::new(
+            peer(),
+            vec![
+                address("/ip4/192.168.1.20/tcp/4001"),
+                address("/ip6/::1/udp/4001/quic-v1"),
+            ],
+        );
+        assert_eq!(Invite::parse(&invite.to_string()).unwrap(), invite);
+    }
+
+    #[test]
+    fn peer_ids_are_stripped_from_addresses() {
+        let peer_id = peer();
+        let invite = Invite::new(
+            peer_id,
+            vec![address(&format!("/ip4/10.0.0.2/tcp/4001/p2p/{peer_id}"))],
+        );
+        assert_eq!(invite.addresses, vec![address("/ip4/10.0.0.2/tcp/4001")]);
+        assert_eq!(
+            invite.to_string(),
+            format!("/ip4/10.0.0.2/tcp/4001/p2p/{peer_id}")
+        );
+    }
+
+    #[test]
+    fn separators_are_lenient() {
+        let peer_id = peer();
+        let text = format!(
+            "/ip4/10.0.0.2/tcp/4001/p2p/{peer_id},\n/ip4/10.0.0.3/tcp/4001/p2p/{peer_id}  "
+        );
+        let invite = Invite::parse(&text).unwrap();
+        assert_eq!(invite.peer_id, peer_id);
+        assert_eq!(invite.addresses.len(), 2);
+    }
+
+    #[test]
+    fn empty_invites_are_rejected() {
+        assert!(Invite::parse("").is_err());
+        assert!(Invite::parse(" \n, ").is_err());
+    }
+
+    #[test]
+    fn mixed_peer_ids_are_rejected() {
+        let text = format!(
+            "/ip4/10.0.0.2/tcp/4001/p2p/{} /ip4/10.0.0.3/tcp/4001/p2p/{}",
+            peer(),
+            peer()
+        );
+        assert!(Invite::parse(&text).is_err());
+    }
+
+    #[test]
+    fn addresses_need_a_peer_id() {
+        assert!(Invite::parse("/ip4/10.0.0.2/tcp/4001").is_err());
+        let text = format!(
+            "/ip4/10.0.0.2/tcp/4001/p2p/{} /ip4/10.0.0.3/tcp/4001",
+            peer()
+        );
+        assert!(Invite::parse(&text).is_err());
+        assert!(Invite::parse("not an address").is_err());
+    }
+}
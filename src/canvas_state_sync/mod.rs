@@ -1,2 +1,11 @@
+pub mod blobs;
+pub mod codec;
+pub mod crdt;
+pub mod crypto;
+pub mod identity;
+pub mod invite;
+pub mod p2p;
+pub mod peers;
+pub mod roles;
+pub mod rooms;
 pub mod sync_types;
-pub mod p2p;
\ No newline at end of file
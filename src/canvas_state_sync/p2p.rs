@@ -1,18 +1,36 @@
 use bincode::{self};
 use futures::stream::StreamExt;
 use libp2p::gossipsub::IdentTopic;
-use libp2p::Swarm;
-use libp2p::{gossipsub, mdns, noise, swarm::NetworkBehaviour, swarm::SwarmEvent, tcp, yamux};
+use libp2p::request_response::{self, ProtocolSupport};
+use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::{
+    gossipsub, identify, mdns, noise, ping, swarm::NetworkBehaviour, swarm::SwarmEvent, tcp, yamux,
+};
+use libp2p::{identity::Keypair, multiaddr::Protocol, Multiaddr, PeerId, StreamProtocol, Swarm};
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tokio::{io, select};
 
-use crate::canvas_state_sync::sync_types::MessageType;
+use crate::canvas_state_sync::sync_types::{
+    MessageType, P2pCommand, P2pEvent, Presence, TransferEvent,
+};
+use crate::playback::unix_time_ms;
 
-use super::sync_types::{ChunkCollector, ChunkedMessage};
+use super::blobs::{BlobCodec, BlobFetcher, BlobRequest, BlobResponse, BlobStore, BLOB_PROTOCOL};
+use super::codec::BincodeCodec;
+use super::crypto::{open, seal, Payload, RoomKey};
+use super::invite::Invite;
+use super::peers::Peers;
+use super::roles::Roster;
+use super::rooms::{Room, RoomAnnouncement, ROOMS_TOPIC};
+use super::sync_types::{
+    decode_chunk, decode_limited, decode_message, split_message, ChunkCollector, ChunkOutcome,
+    ChunkRequest, ChunkResponse, ChunkedMessage, SentMessages, MAX_MESSAGE_SIZE,
+};
 
 const MAX_DATA_TRANSFER_SIZE: usize = 1024 * 1024;
 const MAX_CHUNK_SIZE: usize = MAX_DATA_TRANSFER_SIZE
@@ -23,14 +41,37 @@ const MAX_CHUNK_SIZE: usize = MAX_DATA_TRANSFER_SIZE
 pub struct TestBehavior {
     gossipsub: gossipsub::Behaviour,
     mdns: mdns::tokio::Behaviour,
+    blobs: request_response::Behaviour<BlobCodec>,
+    chunks: request_response::Behaviour<BincodeCodec<ChunkRequest, ChunkResponse>>,
+    ping: ping::Behaviour,
+    identify: identify::Behaviour,
+}
+
+const ROOM_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_PRESENCE_SIZE: usize = 4096;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+const CHUNK_PROTOCOL: StreamProtocol = StreamProtocol::new("/muse/chunks/1");
+
+/// State of messages and blobs in flight.
+struct Transfers {
+    collector: ChunkCollector,
+    sent: SentMessages,
+    blobs: BlobStore,
+    fetcher: BlobFetcher,
+    key: Option<RoomKey>,    // Set in encrypted rooms
+    sealed: HashSet<String>, // Images shared in encrypted rooms, never sent unencrypted
+    roster: Roster,          // Of the current room, from the GUI
 }
 
 pub async fn p2p(
-    mut gui_receiver: mpsc::Receiver<MessageType>,
-    p2p_sender: mpsc::Sender<MessageType>,
+    mut gui_receiver: mpsc::Receiver<P2pCommand>,
+    p2p_sender: mpsc::Sender<P2pEvent>,
     running: Arc<AtomicBool>,
+    blobs: BlobStore,
+    keypair: Keypair,
+    display_name: String,
 ) {
-    let mut swarm = libp2p::SwarmBuilder::with_new_identity()
+    let mut swarm = libp2p::SwarmBuilder::with_existing_identity(keypair)
         .with_tokio()
         .with_tcp(
             tcp::Config::default(),
@@ -40,18 +81,11 @@ pub async fn p2p(
         .unwrap()
         .with_quic()
         .with_behaviour(|key| {
-            let message_id_fn = |_message: &gossipsub::Message| {
-                // Note: Maybe the message should be used for the ID generation, but we'll see in the future.
-                // This seems to work for now
-                let now = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .expect("Time went backwards");
-
-                // Use the number of seconds and nanoseconds as the unique identifier
-                let timestamp = now.as_secs() as u64 * 1_000_000_000 + now.subsec_nanos() as u64;
-
-                // Create a MessageId based on the timestamp
-                gossipsub::MessageId::from(timestamp.to_string())
+            // Every chunk carries a unique message ID and index, so the content identifies it
+            let message_id_fn = |message: &gossipsub::Message| {
+                let mut hasher = DefaultHasher::new();
+                message.data.hash(&mut hasher);
+                gossipsub::MessageId::from(hasher.finish().to_string())
             };
 
             let gossipsub_config = gossipsub::ConfigBuilder::default()
@@ -60,7 +94,7 @@ pub async fn p2p(
                 .message_id_fn(message_id_fn)
                 .max_transmit_size(MAX_DATA_TRANSFER_SIZE)
                 .build()
-                .map_err(|msg| io::Error::new(io::ErrorKind::Other, msg))
+                .map_err(io::Error::other)
                 .unwrap();
 
             let gossipsub = gossipsub::Behaviour::new(
@@ -72,23 +106,72 @@ pub async fn p2p(
             let mdns =
                 mdns::tokio::Behaviour::new(mdns::Config::default(), key.public().to_peer_id())
                     .unwrap();
-            Ok(TestBehavior { gossipsub, mdns })
+            // Image bytes are fetched directly from a peer that has them, instead of being gossiped
+            let blobs = request_response::Behaviour::new(
+                [(BLOB_PROTOCOL, ProtocolSupport::Full)],
+                request_response::Config::default().with_request_timeout(Duration::from_secs(60)),
+            );
+
+            // Chunks lost on the gossip mesh are requested from the author directly
+            let chunks = request_response::Behaviour::new(
+                [(CHUNK_PROTOCOL, ProtocolSupport::Full)],
+                request_response::Config::default(),
+            );
+
+            // The agent version carries the display name shown in other peers' lists
+            let identify = identify::Behaviour::new(
+                identify::Config::new("/muse/id/1".to_owned(), key.public())
+                    .with_agent_version(display_name),
+            );
+
+            Ok(TestBehavior {
+                gossipsub,
+                mdns,
+                blobs,
+                chunks,
+                ping: ping::Behaviour::default(),
+                identify,
+            })
         })
         .unwrap()
         .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
         .build();
 
-    let topic = gossipsub::IdentTopic::new("test-net");
-    swarm.behaviour_mut().gossipsub.subscribe(&topic).unwrap();
+    let _ = p2p_sender
+        .send(P2pEvent::LocalPeerId(swarm.local_peer_id().to_string()))
+        .await;
 
+    let rooms_topic = gossipsub::IdentTopic::new(ROOMS_TOPIC);
     swarm
-        .listen_on("/ip4/0.0.0.0/udp/0/quic-v1".parse().unwrap())
-        .unwrap();
-    swarm
-        .listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap())
+        .behaviour_mut()
+        .gossipsub
+        .subscribe(&rooms_topic)
         .unwrap();
+    // Nothing is synced until the GUI joins a room
+    let mut room: Option<(Room, IdentTopic)> = None;
 
-    let mut chunk_collector = ChunkCollector::new();
+    for address in ["/ip4/0.0.0.0/udp/0/quic-v1", "/ip4/0.0.0.0/tcp/0"] {
+        if let Err(err) = swarm.listen_on(address.parse().unwrap()) {
+            let error = format!("Could not listen on {address}: {err}");
+            let _ = p2p_sender.send(P2pEvent::NetworkError(error)).await;
+        }
+    }
+
+    let mut transfers = Transfers {
+        collector: ChunkCollector::new(),
+        sent: SentMessages::default(),
+        blobs,
+        fetcher: BlobFetcher::default(),
+        key: None,
+        sealed: HashSet::new(),
+        roster: Roster::default(),
+    };
+    let mut peers = Peers::default();
+    // Peers dialed from invites, for networks where mDNS does not find them
+    let mut known: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+    let mut reconnect = tokio::time::interval(RECONNECT_INTERVAL);
+    let mut check_transfers = tokio::time::interval(Duration::from_secs(1));
+    let mut announce_room = tokio::time::interval(ROOM_ANNOUNCE_INTERVAL);
     loop {
         if !running.load(Ordering::Relaxed) {
             break;
@@ -96,13 +179,98 @@ pub async fn p2p(
 
         select! {
             event = swarm.select_next_some() => {
-                handle_swarm_event(&mut swarm, &p2p_sender, event, &mut chunk_collector).await;
+                let room = room.as_ref().map(|(room, _)| room);
+                handle_swarm_event(&mut swarm, &p2p_sender, event, &mut transfers, &mut peers, room).await;
+                if let Some(list) = peers.take_changes() {
+                    let _ = p2p_sender.send(P2pEvent::Peers(list)).await;
+                }
             }
-            Some(message) = gui_receiver.recv() => {
-                handle_sending(&mut swarm, &topic, &message);
+            Some(command) = gui_receiver.recv() => match command {
+                P2pCommand::Broadcast(message) => {
+                    let Some((_, topic)) = &room else {
+                        continue;
+                    };
+                    if let Some(event) = handle_sending(&mut swarm, topic, &message, &mut transfers) {
+                        let _ = p2p_sender.send(P2pEvent::Transfer(event)).await;
+                    }
+                }
+                P2pCommand::Presence(presence) => {
+                    if let Some((room, _)) = &room {
+                        send_presence(&mut swarm, room, &presence, transfers.key.as_ref());
+                    }
+                }
+                P2pCommand::JoinRoom { room: new_room, passphrase } => {
+                    leave_room(&mut swarm, room.take());
+                    transfers.key = None;
+                    transfers.roster = Roster::default();
+                    if new_room.encrypted && passphrase.is_none() {
+                        // Joining without the key would leak the board
+                        println!("Room {} needs a passphrase", new_room.code);
+                        continue;
+                    }
+                    if let Some(passphrase) = passphrase {
+                        match RoomKey::derive(&passphrase, &new_room.code) {
+                            Ok(key) => transfers.key = Some(key),
+                            Err(err) => {
+                                println!("Could not join room {}: {err}", new_room.code);
+                                continue;
+                            }
+                        }
+                    }
+
+                    let topic = gossipsub::IdentTopic::new(new_room.topic());
+                    let presence_topic = gossipsub::IdentTopic::new(new_room.presence_topic());
+                    let gossipsub = &mut swarm.behaviour_mut().gossipsub;
+                    if let Err(err) = gossipsub
+                        .subscribe(&topic)
+                        .and_then(|_| gossipsub.subscribe(&presence_topic))
+                    {
+                        println!("Could not join room {}: {err:?}", new_room.code);
+                        continue;
+                    }
+                    println!("Joined room {} ({})", new_room.name, new_room.code);
+                    room = Some((new_room, topic));
+                    announce_room.reset_immediately();
+                }
+                P2pCommand::LeaveRoom => {
+                    leave_room(&mut swarm, room.take());
+                    transfers.key = None;
+                    transfers.roster = Roster::default();
+                }
+                P2pCommand::Roster { host, roster } => {
+                    if let Some((room, _)) = &mut room {
+                        if room.host.is_empty() && room.hosted_by(&host) {
+                            room.host = host;
+                        }
+                        transfers.roster = roster;
+                    }
+                }
+                P2pCommand::Dial(invite) => {
+                    swarm.behaviour_mut().gossipsub.add_explicit_peer(&invite.peer_id);
+                    dial(&mut swarm, invite.peer_id, invite.addresses.clone());
+                    known.insert(invite.peer_id, invite.addresses);
+                }
+                P2pCommand::Forget(peer_id) => {
+                    known.remove(&peer_id);
+                }
+            },
+            _ = reconnect.tick() => {
+                for (peer_id, addresses) in &known {
+                    if !swarm.is_connected(peer_id) {
+                        dial(&mut swarm, *peer_id, addresses.clone());
+                    }
+                }
+            }
+            _ = announce_room.tick() => {
+                // Announcements from anyone but the host are dropped
+                if let Some((room, _)) = &room {
+                    if room.host == swarm.local_peer_id().to_string() {
+                        announce(&mut swarm, &rooms_topic, room);
+                    }
+                }
             }
-            _ = tokio::time::sleep(Duration::from_secs(5)) => {
-                clear_outdated_chunks(&mut chunk_collector);
+            _ = check_transfers.tick() => {
+                retry_transfers(&mut swarm, &p2p_sender, &mut transfers).await;
             }
         }
     }
@@ -110,113 +278,540 @@ pub async fn p2p(
 
 async fn handle_swarm_event(
     swarm: &mut Swarm<TestBehavior>,
-    p2p_sender: &mpsc::Sender<MessageType>,
+    p2p_sender: &mpsc::Sender<P2pEvent>,
     event: SwarmEvent<TestBehaviorEvent>,
-    chunk_collector: &mut ChunkCollector,
+    transfers: &mut Transfers,
+    peers: &mut Peers,
+    room: Option<&Room>,
 ) {
     match event {
         SwarmEvent::Behaviour(TestBehaviorEvent::Mdns(mdns::Event::Discovered(list))) => {
-            for (peer_id, _multiaddr) in list {
+            for (peer_id, multiaddr) in list {
                 println!("mDNS discovered a new peer: {peer_id}");
+                peers.discovered(peer_id, &multiaddr);
                 swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
             }
         }
         SwarmEvent::Behaviour(TestBehaviorEvent::Mdns(mdns::Event::Expired(list))) => {
             for (peer_id, _multiaddr) in list {
                 println!("mDNS discover peer has expired: {peer_id}");
+                peers.expired(peer_id);
                 swarm
                     .behaviour_mut()
                     .gossipsub
                     .remove_explicit_peer(&peer_id);
             }
         }
+        SwarmEvent::ConnectionEstablished {
+            peer_id, endpoint, ..
+        } => {
+            peers.connected(peer_id, endpoint.get_remote_address());
+        }
+        SwarmEvent::ConnectionClosed {
+            peer_id,
+            num_established: 0,
+            ..
+        } => {
+            peers.disconnected(peer_id);
+        }
+        SwarmEvent::Behaviour(TestBehaviorEvent::Identify(identify::Event::Received {
+            peer_id,
+            info,
+            ..
+        })) => {
+            peers.named(peer_id, info.agent_version);
+        }
+        SwarmEvent::Behaviour(TestBehaviorEvent::Ping(ping::Event {
+            peer,
+            result: Ok(latency),
+            ..
+        })) => {
+            peers.pinged(peer, latency);
+        }
+        SwarmEvent::ListenerError { error, .. } => {
+            let error = format!("Network listener failed: {error}");
+            let _ = p2p_sender.send(P2pEvent::NetworkError(error)).await;
+        }
+        SwarmEvent::ListenerClosed {
+            reason: Err(error), ..
+        } => {
+            let error = format!("Network listener closed: {error}");
+            let _ = p2p_sender.send(P2pEvent::NetworkError(error)).await;
+        }
         SwarmEvent::Behaviour(TestBehaviorEvent::Gossipsub(gossipsub::Event::Message {
-            propagation_source: _peer_id,
+            propagation_source,
             message_id: _id,
             message,
         })) => {
-            let deserialized_chunk: Result<ChunkedMessage, _> = bincode::deserialize(&message.data);
+            if message.topic.as_str() == ROOMS_TOPIC {
+                if let Ok(announcement) = decode_limited::<RoomAnnouncement>(&message.data, 1024) {
+                    // Messages are signed, and the join code names the host's peer ID,
+                    // so nobody else can claim to host the room
+                    let room = &announcement.room;
+                    let source = message.source.map(|source| source.to_string());
+                    if source.as_ref() != Some(&room.host) || !room.hosted_by(&room.host) {
+                        println!(
+                            "Ignoring an announcement of room {} not sent by its host",
+                            announcement.room.code
+                        );
+                        return;
+                    }
+                    let _ = p2p_sender
+                        .send(P2pEvent::RoomAnnounced(announcement.room))
+                        .await;
+                }
+                return;
+            }
+            // Leftovers from a room that was just left
+            let Some(room) = room else {
+                return;
+            };
+            if message.topic.as_str() == room.presence_topic() {
+                let Some(source) = message.source else {
+                    return;
+                };
+                if let Some(presence) = read_presence(&message.data, transfers.key.as_ref()) {
+                    let peer = source.to_string();
+                    let _ = p2p_sender.send(P2pEvent::Presence { peer, presence }).await;
+                }
+                return;
+            }
+            if message.topic.as_str() != room.topic() {
+                return;
+            }
 
-            if let Ok(ChunkedMessage {
-                id,
-                chunk_index,
-                total_chunks,
-                data,
-            }) = deserialized_chunk
-            {
-                chunk_collector.add_chunk(id, chunk_index, total_chunks, data);
-
-                // If the message is complete, reassemble it
-                // When message is reassembled, it should be removed from memory.
-                // What happens if message is never reassembled?
-                if chunk_collector.is_complete(id) {
-                    if let Some(full_message) = chunk_collector.reassemble(id) {
-                        let deserialized_message: Result<MessageType, _> =
-                            bincode::deserialize(&full_message);
-                        if let Ok(msg) = deserialized_message {
-                            println!("Full message reassembled and deserialized");
-                            let result = p2p_sender.send(msg).await;
-                            println!("Message sent back to GUI");
+            if let Ok(chunk) = decode_chunk(&message.data) {
+                // Messages are signed, so the source is the author
+                let source = message.source.unwrap_or(propagation_source);
+                receive_chunk(swarm, p2p_sender, transfers, source, chunk).await;
+            }
+        }
+        SwarmEvent::Behaviour(TestBehaviorEvent::Chunks(request_response::Event::Message {
+            peer,
+            message,
+        })) => match message {
+            request_response::Message::Request {
+                request, channel, ..
+            } => {
+                let chunks = if room.is_some_and(|room| is_member(room, &transfers.roster, peer)) {
+                    transfers.sent.chunks(&request)
+                } else {
+                    println!(
+                        "Not resending chunks of message {} to {peer}, who is not a member",
+                        request.id
+                    );
+                    Vec::new()
+                };
+                let response = ChunkResponse { chunks };
+                if swarm
+                    .behaviour_mut()
+                    .chunks
+                    .send_response(channel, response)
+                    .is_err()
+                {
+                    println!(
+                        "Could not resend chunks of message {} to {peer}",
+                        request.id
+                    );
+                }
+            }
+            request_response::Message::Response { response, .. } => {
+                for chunk in response.chunks {
+                    receive_chunk(swarm, p2p_sender, transfers, peer, chunk).await;
+                }
+            }
+        },
+        SwarmEvent::Behaviour(TestBehaviorEvent::Chunks(
+            request_response::Event::OutboundFailure { peer, error, .. },
+        )) => {
+            // The next check asks again, until the message is given up on
+            println!("Requesting missing chunks from {peer} failed: {error}");
+        }
+        SwarmEvent::Behaviour(TestBehaviorEvent::Blobs(request_response::Event::Message {
+            peer,
+            message,
+        })) => match message {
+            request_response::Message::Request {
+                request, channel, ..
+            } => {
+                let response = BlobResponse {
+                    bytes: serve_blob(swarm, transfers, room, peer, &request.hash),
+                };
+                if swarm
+                    .behaviour_mut()
+                    .blobs
+                    .send_response(channel, response)
+                    .is_err()
+                {
+                    println!("Could not send image {} to {peer}", request.hash);
+                }
+            }
+            request_response::Message::Response {
+                request_id,
+                response,
+            } => {
+                let Some(hash) = transfers.fetcher.finished(request_id) else {
+                    return;
+                };
+                let bytes = response
+                    .bytes
+                    .map(|payload| open(transfers.key.as_ref(), payload));
+                let verified = match bytes {
+                    Some(Err(err)) => {
+                        let _ = p2p_sender.send(P2pEvent::Decryption(err)).await;
+                        false
+                    }
+                    Some(Ok(bytes)) => {
+                        let verified = transfers.blobs.insert_verified(&hash, bytes);
+                        if !verified {
+                            println!("Image {hash} from {peer} does not match its hash");
                         }
+                        verified
                     }
+                    None => false,
+                };
+
+                if verified {
+                    transfers.fetcher.succeeded(&hash);
+                    let _ = p2p_sender.send(P2pEvent::BlobReceived(hash)).await;
+                } else {
+                    let next = transfers.fetcher.failed(&hash);
+                    request_blob(swarm, &mut transfers.fetcher, &hash, next);
                 }
             }
+        },
+        SwarmEvent::Behaviour(TestBehaviorEvent::Blobs(
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+                ..
+            },
+        )) => {
+            if let Some(hash) = transfers.fetcher.finished(request_id) {
+                println!("Fetching image {hash} from {peer} failed: {error}");
+                let next = transfers.fetcher.failed(&hash);
+                request_blob(swarm, &mut transfers.fetcher, &hash, next);
+            }
         }
         SwarmEvent::NewListenAddr { address, .. } => {
             println!("Local node is listening on {address}");
+            send_invite(swarm, p2p_sender).await;
+        }
+        SwarmEvent::ExpiredListenAddr { .. } => {
+            send_invite(swarm, p2p_sender).await;
+        }
+        SwarmEvent::OutgoingConnectionError {
+            peer_id: Some(peer_id),
+            error,
+            ..
+        } => {
+            println!("Could not connect to {peer_id}: {error}");
         }
         _ => {}
     }
 }
 
-fn handle_sending(swarm: &mut Swarm<TestBehavior>, topic: &IdentTopic, message: &MessageType) {
-    let serialized_message = bincode::serialize(message).expect("failed to serialise");
+/// Whether `peer` is the host of `room` or was approved by them.
+fn is_member(room: &Room, roster: &Roster, peer: PeerId) -> bool {
+    let peer = peer.to_string();
+    peer == room.host || roster.admits(&peer)
+}
 
-    let message_id = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
+/// Image bytes asked for by `peer`. Only members of the current room get them,
+/// and images shared in an encrypted room are never sent unencrypted.
+fn serve_blob(
+    swarm: &Swarm<TestBehavior>,
+    transfers: &Transfers,
+    room: Option<&Room>,
+    peer: PeerId,
+    hash: &str,
+) -> Option<Payload> {
+    let room = room?;
+    let topic = IdentTopic::new(room.topic()).hash();
+    let in_room = swarm
+        .behaviour()
+        .gossipsub
+        .all_peers()
+        .any(|(member, topics)| *member == peer && topics.contains(&&topic));
+    if !in_room || !is_member(room, &transfers.roster, peer) {
+        println!("Not sending image {hash} to {peer}, who is not in the room");
+        return None;
+    }
+    if transfers.key.is_none() && transfers.sealed.contains(hash) {
+        println!("Not sending image {hash} unencrypted, it was shared in an encrypted room");
+        return None;
+    }
+    let bytes = transfers.blobs.get(hash)?;
+    Some(seal(transfers.key.as_ref(), bytes.to_vec()))
+}
 
-    let total_chunks = (serialized_message.len() as f64 / MAX_CHUNK_SIZE as f64).ceil() as u32;
+/// Collects a chunk from gossip or a retransmission, and hands completed messages to the GUI.
+async fn receive_chunk(
+    swarm: &mut Swarm<TestBehavior>,
+    p2p_sender: &mpsc::Sender<P2pEvent>,
+    transfers: &mut Transfers,
+    source: PeerId,
+    chunk: ChunkedMessage,
+) {
+    let id = chunk.id;
+    let full_message = match transfers.collector.add_chunk(source, chunk) {
+        ChunkOutcome::Pending => {
+            if let Some((received, total)) = transfers.collector.progress(source, id) {
+                let event = TransferEvent::Progress {
+                    id,
+                    received,
+                    total,
+                };
+                let _ = p2p_sender.send(P2pEvent::Transfer(event)).await;
+            }
+            return;
+        }
+        ChunkOutcome::Rejected(reason) => {
+            println!("Ignoring chunk of message {id} from {source}: {reason}");
+            return;
+        }
+        ChunkOutcome::Corrupt => {
+            println!("Message {id} from {source} does not match its hash, requesting it again");
+            return;
+        }
+        ChunkOutcome::Complete(full_message) => full_message,
+    };
 
-    for (index, chunk) in serialized_message.chunks(MAX_CHUNK_SIZE).enumerate() {
-        let chunk_message = ChunkedMessage {
-            id: message_id,
-            chunk_index: index as u32,
-            total_chunks,
-            data: chunk.to_vec(),
-        };
+    let payload = match decode_limited::<Payload>(&full_message, MAX_MESSAGE_SIZE) {
+        Ok(payload) => payload,
+        Err(err) => {
+            let event = TransferEvent::Failed {
+                id,
+                error: format!("Unreadable message from {source}: {err}"),
+            };
+            let _ = p2p_sender.send(P2pEvent::Transfer(event)).await;
+            return;
+        }
+    };
+    let full_message = match open(transfers.key.as_ref(), payload) {
+        Ok(full_message) => full_message,
+        Err(err) => {
+            println!("Could not open message {id} from {source}: {err}");
+            let _ = p2p_sender.send(P2pEvent::Decryption(err)).await;
+            return;
+        }
+    };
 
-        let serialized_chunk =
-            bincode::serialize(&chunk_message).expect("Failed to serialize chunk");
+    let msg = match decode_message(&full_message) {
+        Ok(msg) => msg,
+        Err(err) => {
+            let event = TransferEvent::Failed {
+                id,
+                error: format!("Unreadable message from {source}: {err}"),
+            };
+            let _ = p2p_sender.send(P2pEvent::Transfer(event)).await;
+            return;
+        }
+    };
+    println!("Full message reassembled and deserialized");
+    if transfers.key.is_some() {
+        let hashes = msg.blob_hashes().into_iter().map(str::to_owned);
+        transfers.sealed.extend(hashes);
+    }
 
-        if let Err(e) = swarm
-            .behaviour_mut()
-            .gossipsub
-            .publish(topic.clone(), serialized_chunk)
+    // The author announced the images, so it is asked first
+    let mut peers = vec![source];
+    peers.extend(swarm.connected_peers().copied());
+    for hash in msg.blob_hashes() {
+        if !transfers.blobs.contains(hash) {
+            let peer = transfers.fetcher.want(hash, peers.iter().copied());
+            request_blob(swarm, &mut transfers.fetcher, hash, peer);
+        }
+    }
+
+    let completed = TransferEvent::Completed {
+        id,
+        size: full_message.len(),
+    };
+    let _ = p2p_sender.send(P2pEvent::Transfer(completed)).await;
+    let event = P2pEvent::Message {
+        from: source.to_string(),
+        message: msg,
+    };
+    if p2p_sender.send(event).await.is_ok() {
+        println!("Message sent back to GUI");
+    }
+}
+
+/// Requests chunks of stalled messages, and reports the ones that were given up on.
+async fn retry_transfers(
+    swarm: &mut Swarm<TestBehavior>,
+    p2p_sender: &mpsc::Sender<P2pEvent>,
+    transfers: &mut Transfers,
+) {
+    let now = Instant::now();
+    for (source, request) in transfers.collector.stalled(now) {
+        println!(
+            "Requesting {} missing chunks of message {} from {source}",
+            request.chunk_indices.len(),
+            request.id
+        );
+        swarm.behaviour_mut().chunks.send_request(&source, request);
+    }
+    for event in transfers.collector.expire(now) {
+        let _ = p2p_sender.send(P2pEvent::Transfer(event)).await;
+    }
+    transfers.sent.expire(now);
+}
+
+fn leave_room(swarm: &mut Swarm<TestBehavior>, room: Option<(Room, IdentTopic)>) {
+    if let Some((room, topic)) = room {
+        let presence_topic = IdentTopic::new(room.presence_topic());
+        let gossipsub = &mut swarm.behaviour_mut().gossipsub;
+        if let Err(err) = gossipsub
+            .unsubscribe(&topic)
+            .and_then(|_| gossipsub.unsubscribe(&presence_topic))
         {
-            println!("Publish error: {e:?}");
+            println!("Could not leave room {}: {err:?}", room.code);
+            return;
         }
+        println!("Left room {} ({})", room.name, room.code);
     }
 }
 
-fn clear_outdated_chunks(chunk_collector: &mut ChunkCollector) {
-    let now = SystemTime::now();
-    let delete_duration = Duration::from_secs(5);
+fn dial(swarm: &mut Swarm<TestBehavior>, peer_id: PeerId, addresses: Vec<Multiaddr>) {
+    let opts = DialOpts::peer_id(peer_id).addresses(addresses).build();
+    if let Err(err) = swarm.dial(opts) {
+        println!("Could not dial {peer_id}: {err}");
+    }
+}
 
-    let mut ids_to_remove = vec![];
-    for (id, time) in chunk_collector.chunk_times.iter() {
-        let time_elapesed = now.duration_since(*time);
+/// Invite with the addresses other machines can reach. Loopback is only used if there is nothing else.
+async fn send_invite(swarm: &Swarm<TestBehavior>, p2p_sender: &mpsc::Sender<P2pEvent>) {
+    let listeners: Vec<Multiaddr> = swarm.listeners().cloned().collect();
+    let reachable: Vec<Multiaddr> = listeners
+        .iter()
+        .filter(|address| {
+            !address.iter().any(|protocol| match protocol {
+                Protocol::Ip4(ip) => ip.is_loopback(),
+                Protocol::Ip6(ip) => ip.is_loopback(),
+                _ => false,
+            })
+        })
+        .cloned()
+        .collect();
+    let addresses = if reachable.is_empty() {
+        listeners
+    } else {
+        reachable
+    };
+    let invite = Invite::new(*swarm.local_peer_id(), addresses);
+    let _ = p2p_sender.send(P2pEvent::Invite(invite.to_string())).await;
+}
 
-        match time_elapesed {
-            Ok(time) => {
-                if time > delete_duration {
-                    ids_to_remove.push(id.to_owned());
-                }
+/// Lets other peers on the LAN list the room.
+fn announce(swarm: &mut Swarm<TestBehavior>, rooms_topic: &IdentTopic, room: &Room) {
+    let announcement = RoomAnnouncement {
+        room: room.clone(),
+        sent_at_ms: unix_time_ms(),
+    };
+    let data = bincode::serialize(&announcement).expect("Failed to serialize announcement");
+    match swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(rooms_topic.clone(), data)
+    {
+        Ok(_) | Err(gossipsub::PublishError::InsufficientPeers) => {}
+        Err(err) => println!("Announcing room failed: {err:?}"),
+    }
+}
+
+/// Publishes a cursor update. Presence is not chunked or retransmitted, a lost update is
+/// replaced by the next one.
+fn send_presence(
+    swarm: &mut Swarm<TestBehavior>,
+    room: &Room,
+    presence: &Presence,
+    key: Option<&RoomKey>,
+) {
+    let data = bincode::serialize(presence).expect("Failed to serialize presence");
+    let data = bincode::serialize(&seal(key, data)).expect("Failed to serialize presence");
+    let topic = IdentTopic::new(room.presence_topic());
+    match swarm.behaviour_mut().gossipsub.publish(topic, data) {
+        Ok(_)
+        | Err(gossipsub::PublishError::InsufficientPeers)
+        | Err(gossipsub::PublishError::Duplicate) => {}
+        Err(err) => println!("Sending presence failed: {err:?}"),
+    }
+}
+
+/// Presence that cannot be read is dropped quietly, the board sync reports key problems.
+fn read_presence(data: &[u8], key: Option<&RoomKey>) -> Option<Presence> {
+    let payload = decode_limited::<Payload>(data, MAX_PRESENCE_SIZE).ok()?;
+    let data = open(key, payload).ok()?;
+    decode_limited(&data, MAX_PRESENCE_SIZE).ok()
+}
+
+/// Asks `peer` for a blob. `None` means a request is already running, or nobody is left to ask.
+fn request_blob(
+    swarm: &mut Swarm<TestBehavior>,
+    fetcher: &mut BlobFetcher,
+    hash: &str,
+    peer: Option<PeerId>,
+) {
+    let Some(peer) = peer else {
+        return;
+    };
+    let request_id = swarm.behaviour_mut().blobs.send_request(
+        &peer,
+        BlobRequest {
+            hash: hash.to_owned(),
+        },
+    );
+    fetcher.started(request_id, hash);
+}
+
+/// Publishes a message in chunks, and keeps them for retransmission.
+/// Returns a failure event if the message could not be published.
+fn handle_sending(
+    swarm: &mut Swarm<TestBehavior>,
+    topic: &IdentTopic,
+    message: &MessageType,
+    transfers: &mut Transfers,
+) -> Option<TransferEvent> {
+    if transfers.key.is_some() {
+        let hashes = message.blob_hashes().into_iter().map(str::to_owned);
+        transfers.sealed.extend(hashes);
+    }
+    let serialized_message = bincode::serialize(message).expect("failed to serialise");
+    let payload = seal(transfers.key.as_ref(), serialized_message);
+    let serialized_message = bincode::serialize(&payload).expect("failed to serialise");
+    if serialized_message.len() > MAX_MESSAGE_SIZE {
+        // Peers would drop it anyway
+        return Some(TransferEvent::Failed {
+            id: 0,
+            error: format!("Message of {} bytes is too large", serialized_message.len()),
+        });
+    }
+    let chunks = split_message(&serialized_message, MAX_CHUNK_SIZE);
+    let id = chunks[0].id;
+
+    for chunk in &chunks {
+        let serialized_chunk = bincode::serialize(chunk).expect("Failed to serialize chunk");
+
+        match swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(topic.clone(), serialized_chunk)
+        {
+            Ok(_) => {}
+            // Nobody to send to is not a failed transfer
+            Err(gossipsub::PublishError::InsufficientPeers) => return None,
+            Err(err) => {
+                return Some(TransferEvent::Failed {
+                    id,
+                    error: format!("Publishing failed: {err}"),
+                })
             }
-            Err(_) => ids_to_remove.push(id.to_owned()),
         }
     }
 
-    let _ = ids_to_remove.iter().map(|id| chunk_collector.remove_chunks(id));
+    transfers.sent.insert(chunks);
+    None
 }
@@ -0,0 +1,112 @@
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use std::{collections::HashMap, time::Duration};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Transport {
+    Tcp,
+    Quic,
+}
+
+impl Transport {
+    fn of(address: &Multiaddr) -> Self {
+        if address
+            .iter()
+            .any(|protocol| matches!(protocol, Protocol::QuicV1 | Protocol::Quic))
+        {
+            Transport::Quic
+        } else {
+            Transport::Tcp
+        }
+    }
+}
+
+impl std::fmt::Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Transport::Tcp => "TCP",
+            Transport::Quic => "QUIC",
+        })
+    }
+}
+
+/// What the peer list shows about another peer.
+#[derive(Clone, Debug)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub name: Option<String>, // Sent by the peer once connected
+    pub address: String,
+    pub transport: Transport,
+    pub latency: Option<Duration>,
+    pub connected: bool, // False while only found by mDNS
+}
+
+/// Peers found on the network. Every change marks the list dirty, so the GUI gets a fresh copy.
+#[derive(Default)]
+pub struct Peers {
+    peers: HashMap<PeerId, PeerInfo>,
+    dirty: bool,
+}
+
+impl Peers {
+    fn entry(&mut self, peer: PeerId, address: &Multiaddr) -> &mut PeerInfo {
+        self.dirty = true;
+        self.peers.entry(peer).or_insert_with(|| PeerInfo {
+            peer_id: peer.to_string(),
+            name: None,
+            address: address.to_string(),
+            transport: Transport::of(address),
+            latency: None,
+            connected: false,
+        })
+    }
+
+    pub fn discovered(&mut self, peer: PeerId, address: &Multiaddr) {
+        self.entry(peer, address);
+    }
+
+    /// mDNS stopped seeing the peer. Connected peers stay until the connection closes.
+    pub fn expired(&mut self, peer: PeerId) {
+        if self.peers.get(&peer).is_some_and(|info| !info.connected) {
+            self.peers.remove(&peer);
+            self.dirty = true;
+        }
+    }
+
+    pub fn connected(&mut self, peer: PeerId, address: &Multiaddr) {
+        let info = self.entry(peer, address);
+        info.address = address.to_string();
+        info.transport = Transport::of(address);
+        info.connected = true;
+    }
+
+    /// The last connection to the peer closed.
+    pub fn disconnected(&mut self, peer: PeerId) {
+        if self.peers.remove(&peer).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    pub fn named(&mut self, peer: PeerId, name: String) {
+        if let Some(info) = self.peers.get_mut(&peer) {
+            info.name = (!name.is_empty()).then_some(name);
+            self.dirty = true;
+        }
+    }
+
+    pub fn pinged(&mut self, peer: PeerId, latency: Duration) {
+        if let Some(info) = self.peers.get_mut(&peer) {
+            info.latency = Some(latency);
+            self.dirty = true;
+        }
+    }
+
+    /// Copy of the list if it changed since the last call.
+    pub fn take_changes(&mut self) -> Option<Vec<PeerInfo>> {
+        if !std::mem::take(&mut self.dirty) {
+            return None;
+        }
+        let mut list: Vec<PeerInfo> = self.peers.values().cloned().collect();
+        list.sort_by(|a, b| (!a.connected, &a.peer_id).cmp(&(!b.connected, &b.peer_id)));
+        Some(list)
+    }
+}
@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::playback::unix_time_ms;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    Host, // Created the room, manages the roster
+    Editor,
+    Viewer,
+}
+
+impl Role {
+    pub fn can_edit(self) -> bool {
+        matches!(self, Role::Host | Role::Editor)
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Role::Host => "Host",
+            Role::Editor => "Editor",
+            Role::Viewer => "Viewer",
+        })
+    }
+}
+
+/// Who may do what in a room. Only rosters sent by the host count,
+/// and a newer revision replaces the whole roster.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Roster {
+    pub revision: u64,
+    pub members: HashMap<String, Role>, // Peer ID -> role
+    pub banned: HashSet<String>,        // Rejected or kicked, ignored until approved again
+}
+
+impl Roster {
+    pub fn new(host: &str) -> Self {
+        let mut roster = Self::default();
+        roster.members.insert(host.to_owned(), Role::Host);
+        roster.bump();
+        roster
+    }
+
+    // Wall clock based, so a host that restarts without its saved roster still wins
+    fn bump(&mut self) {
+        self.revision = (self.revision + 1).max(unix_time_ms());
+    }
+
+    pub fn role(&self, peer: &str) -> Option<Role> {
+        self.members.get(peer).copied()
+    }
+
+    pub fn is_banned(&self, peer: &str) -> bool {
+        self.banned.contains(peer)
+    }
+
+    /// Whether `peer` was approved and not removed since. Only they are sent the room's data.
+    pub fn admits(&self, peer: &str) -> bool {
+        self.role(peer).is_some() && !self.is_banned(peer)
+    }
+
+    /// Whether `peer` may change the board of a room hosted by `host`.
+    pub fn can_edit(&self, host: &str, peer: &str) -> bool {
+        peer == host || (self.admits(peer) && self.role(peer).is_some_and(Role::can_edit))
+    }
+
+    pub fn set_role(&mut self, peer: &str, role: Role) {
+        self.banned.remove(peer);
+        self.members.insert(peer.to_owned(), role);
+        self.bump();
+    }
+
+    /// Removes a peer. Banned peers' requests are not shown to the host again.
+    pub fn remove(&mut self, peer: &str) {
+        self.members.remove(peer);
+        self.banned.insert(peer.to_owned());
+        self.bump();
+    }
+
+    /// Takes `other` if it is newer. Returns whether anything changed.
+    pub fn merge(&mut self, other: Roster) -> bool {
+        if other.revision <= self.revision {
+            return false;
+        }
+        *self = other;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: &str = "host";
+
+    #[test]
+    fn stale_rosters_are_ignored() {
+        let mut received = Roster::new(HOST);
+        let stale = received.clone();
+        let mut newer = received.clone();
+        newer.set_role("alice", Role::Editor);
+
+        assert!(received.merge(newer.clone()));
+        assert!(!received.merge(stale));
+        assert!(!received.merge(newer));
+        assert_eq!(received.role("alice"), Some(Role::Editor));
+    }
+
+    #[test]
+    fn kicked_peers_are_not_admitted() {
+        let mut roster = Roster::new(HOST);
+        roster.set_role("alice", Role::Editor);
+        assert!(roster.admits("alice"));
+
+        roster.remove("alice");
+        assert!(!roster.admits("alice"));
+        assert!(roster.is_banned("alice"));
+        assert!(!roster.can_edit(HOST, "alice"));
+    }
+
+    #[test]
+    fn approving_a_banned_peer_unbans_them() {
+        let mut roster = Roster::new(HOST);
+        roster.remove("alice");
+        roster.set_role("alice", Role::Viewer);
+        assert!(!roster.is_banned("alice"));
+        assert!(roster.admits("alice"));
+
+        // A roster that lists a peer as both is treated as banned
+        roster.banned.insert("alice".to_owned());
+        assert!(!roster.admits("alice"));
+    }
+
+    #[test]
+    fn only_hosts_and_editors_can_edit() {
+        let mut roster = Roster::new(HOST);
+        roster.set_role("editor", Role::Editor);
+        roster.set_role("viewer", Role::Viewer);
+
+        assert!(roster.can_edit(HOST, HOST));
+        assert!(roster.can_edit(HOST, "editor"));
+        assert!(!roster.can_edit(HOST, "viewer"));
+        assert!(!roster.can_edit(HOST, "stranger"));
+    }
+}
@@ -0,0 +1,128 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Topic where peers announce the rooms they are in, so others on the LAN can find them.
+pub const ROOMS_TOPIC: &str = "muse-rooms";
+
+// No 0/O, 1/I/L, so codes survive being read out loud
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+// A random part, then a part derived from the host's peer ID. About 40 bits of the latter
+// keep anyone from generating a key that passes for the host of an existing room.
+const ROOM_PART_LENGTH: usize = 6;
+const HOST_PART_LENGTH: usize = 8;
+const CODE_LENGTH: usize = ROOM_PART_LENGTH + HOST_PART_LENGTH;
+
+/// Sync session. Only peers in the same room see each other's boards.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Room {
+    pub name: String,
+    pub code: String,
+    pub encrypted: bool, // Peers need the passphrase to read anything
+    pub host: String,    // Peer ID of the creator, empty until learned from an announcement
+}
+
+/// Part of a join code that only the host's peer ID hashes to.
+fn host_part(host: &str) -> String {
+    Sha256::digest(host.as_bytes())[..HOST_PART_LENGTH]
+        .iter()
+        .map(|byte| CODE_ALPHABET[*byte as usize % CODE_ALPHABET.len()] as char)
+        .collect()
+}
+
+impl Room {
+    /// New room with a random join code that ends in the host's part.
+    pub fn create(name: &str, encrypted: bool, host: String) -> Self {
+        let mut rng = rand::thread_rng();
+        let mut code: String = (0..ROOM_PART_LENGTH)
+            .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+            .collect();
+        code.push_str(&host_part(&host));
+        let name = name.trim();
+        Self {
+            name: if name.is_empty() {
+                "Untitled room".to_owned()
+            } else {
+                name.to_owned()
+            },
+            code,
+            encrypted,
+            host,
+        }
+    }
+
+    /// Whether the join code was made by `host`. Announcements and rosters are only
+    /// trusted from the peer the code names, whoever else claims to host the room.
+    pub fn hosted_by(&self, host: &str) -> bool {
+        !host.is_empty()
+            && self.code.len() == CODE_LENGTH
+            && self.code[ROOM_PART_LENGTH..] == host_part(host)
+    }
+
+    /// Join code split in two, which is easier to read out loud.
+    pub fn display_code(&self) -> String {
+        match self.code.split_at_checked(ROOM_PART_LENGTH) {
+            Some((room, host)) => format!("{room}-{host}"),
+            None => self.code.clone(),
+        }
+    }
+
+    /// Gossipsub topic carrying the room's board sync.
+    pub fn topic(&self) -> String {
+        format!("muse-room-{}", self.code)
+    }
+
+    /// Gossipsub topic carrying cursors and viewports. Kept apart from the board sync,
+    /// since presence is sent often and losing some of it does not matter.
+    pub fn presence_topic(&self) -> String {
+        format!("muse-room-{}-presence", self.code)
+    }
+}
+
+/// Cleans up a typed join code. Returns `None` if it cannot be a code.
+pub fn normalize_code(code: &str) -> Option<String> {
+    let code: String = code
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    let valid = code.len() == CODE_LENGTH && code.bytes().all(|c| CODE_ALPHABET.contains(&c));
+    valid.then_some(code)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RoomAnnouncement {
+    pub room: Room,
+    pub sent_at_ms: u64, // Keeps repeated announcements from being dropped as duplicates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity::Keypair;
+
+    fn peer() -> String {
+        Keypair::generate_ed25519()
+            .public()
+            .to_peer_id()
+            .to_string()
+    }
+
+    #[test]
+    fn codes_name_their_host() {
+        let host = peer();
+        let room = Room::create("Review", false, host.clone());
+        assert!(room.hosted_by(&host));
+        assert!(!room.hosted_by(&peer()));
+        assert!(!room.hosted_by(""));
+    }
+
+    #[test]
+    fn typed_codes_are_normalized() {
+        let room = Room::create("Review", false, peer());
+        let typed = room.display_code().to_lowercase().replace('-', " - ");
+        assert_eq!(normalize_code(&typed), Some(room.code));
+        assert_eq!(normalize_code("ABCDEF"), None);
+        assert_eq!(normalize_code("ABCDEF-GHJKMN01"), None);
+    }
+}
@@ -1,79 +1,497 @@
-use std::collections::HashMap;
+use bincode::Options;
+use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
 
-use crate::canvas_app::App;
+use crate::{
+    board::ItemId,
+    canvas_app::App,
+    canvas_state_sync::{
+        crdt::{Board, Operation, OperationKind, Stamp},
+        crypto::OpenError,
+        invite::Invite,
+        peers::PeerInfo,
+        roles::Roster,
+        rooms::Room,
+    },
+};
 
-
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ChunkedMessage {
-    pub id: u64,
+    pub id: u64,        // Random, so messages sent close together never collide
+    pub hash: [u8; 32], // SHA-256 of the whole message
     pub chunk_index: u32,
     pub total_chunks: u32,
     pub data: Vec<u8>,
 }
 
+/// Splits a serialized message into chunks that fit into a gossipsub message.
+pub fn split_message(message: &[u8], chunk_size: usize) -> Vec<ChunkedMessage> {
+    let id = rand::random();
+    let hash = Sha256::digest(message).into();
+    let total_chunks = message.len().div_ceil(chunk_size).max(1) as u32;
+
+    let mut chunks: Vec<ChunkedMessage> = message
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(index, data)| ChunkedMessage {
+            id,
+            hash,
+            chunk_index: index as u32,
+            total_chunks,
+            data: data.to_vec(),
+        })
+        .collect();
+    if chunks.is_empty() {
+        chunks.push(ChunkedMessage {
+            id,
+            hash,
+            chunk_index: 0,
+            total_chunks,
+            data: vec![],
+        });
+    }
+    chunks
+}
+
+/// Asks the author of a message for the chunks that never arrived.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChunkRequest {
+    pub id: u64,
+    pub chunk_indices: Vec<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChunkResponse {
+    pub chunks: Vec<ChunkedMessage>, // Empty if the author no longer has the message
+}
+
+// Missing chunks are requested once nothing arrived for this long
+const STALL_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_RETRIES: u32 = 3;
+// Completed messages are remembered this long, so late duplicates are not delivered again
+const COMPLETED_MEMORY: Duration = Duration::from_secs(120);
+const MAX_COMPLETED: usize = 4096;
+
+// Limits on what peers can make this client buffer. Image bytes are fetched separately,
+// so messages stay far below these.
+pub const MAX_CHUNK_DATA: usize = 1024 * 1024;
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+const MAX_TOTAL_CHUNKS: u32 = 1024;
+const MAX_PARTIAL_PER_PEER: usize = 32;
+const PEER_BUFFER: usize = 32 * 1024 * 1024;
+const TOTAL_BUFFER: usize = 128 * 1024 * 1024;
+
+/// Bincode options bounding how much a decoded value can allocate.
+/// Otherwise the same encoding as `bincode::serialize`.
+fn bincode_options(limit: usize) -> impl bincode::Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit as u64)
+}
+
+/// Decodes a chunk received from the network.
+pub fn decode_chunk(bytes: &[u8]) -> bincode::Result<ChunkedMessage> {
+    bincode_options(MAX_CHUNK_DATA + 1024).deserialize(bytes)
+}
+
+/// Decodes a reassembled message.
+pub fn decode_message(bytes: &[u8]) -> bincode::Result<MessageType> {
+    bincode_options(MAX_MESSAGE_SIZE).deserialize(bytes)
+}
+
+/// Decodes a request-response payload. `limit` should match what was read from the stream.
+pub fn decode_limited<T: serde::de::DeserializeOwned>(
+    bytes: &[u8],
+    limit: usize,
+) -> bincode::Result<T> {
+    bincode_options(limit).deserialize(bytes)
+}
+
+pub enum ChunkOutcome {
+    Pending,
+    Complete(Vec<u8>),
+    Corrupt, // Reassembled message did not match its hash, every chunk is requested again
+    Rejected(&'static str),
+}
+
+struct PartialMessage {
+    hash: [u8; 32],
+    chunks: Vec<Option<Vec<u8>>>,
+    size: usize, // Bytes buffered so far
+    last_progress: Instant,
+    retries: u32,
+}
+
+impl PartialMessage {
+    fn missing(&self) -> Vec<u32> {
+        (0..self.chunks.len() as u32)
+            .filter(|index| self.chunks[*index as usize].is_none())
+            .collect()
+    }
+}
+
+/// Reassembles chunked messages, keyed by author and message ID.
+/// Chunks are validated, and the memory a peer can tie up is bounded, per peer and in total.
 pub struct ChunkCollector {
-    chunks: HashMap<u64, Vec<Option<Vec<u8>>>>,       // Mapping message ID -> list of chunks
-    chunk_sizes: HashMap<u64, u32>,                   // Mapping message ID -> total number of chunks
-    pub chunk_times: HashMap<u64, std::time::SystemTime>  // Mapping message ID -> TimeStamp of the message
+    messages: HashMap<(PeerId, u64), PartialMessage>,
+    completed: HashMap<(PeerId, u64), Instant>,
+    buffered: HashMap<PeerId, usize>, // Bytes buffered per peer
+    total_buffered: usize,
 }
 
 impl ChunkCollector {
     pub fn new() -> Self {
         ChunkCollector {
-            chunks: HashMap::new(),
-            chunk_sizes: HashMap::new(),
-            chunk_times: HashMap::new(),
+            messages: HashMap::new(),
+            completed: HashMap::new(),
+            buffered: HashMap::new(),
+            total_buffered: 0,
         }
     }
 
-    pub fn remove_chunks(&mut self, id: &u64) {
-        self.chunks.remove(id);
-        self.chunk_sizes.remove(id);
-        self.chunk_times.remove(id);
+    pub fn add_chunk(&mut self, source: PeerId, chunk: ChunkedMessage) -> ChunkOutcome {
+        let key = (source, chunk.id);
+        if self.completed.contains_key(&key) {
+            return ChunkOutcome::Pending;
+        }
+        if chunk.total_chunks == 0 || chunk.total_chunks > MAX_TOTAL_CHUNKS {
+            return ChunkOutcome::Rejected("invalid chunk count");
+        }
+        if chunk.chunk_index >= chunk.total_chunks {
+            return ChunkOutcome::Rejected("chunk index out of range");
+        }
+        if chunk.data.len() > MAX_CHUNK_DATA {
+            return ChunkOutcome::Rejected("chunk too large");
+        }
+
+        let now = Instant::now();
+        let message = match self.messages.get(&key) {
+            // Chunks that disagree with the first one are not mixed into the message
+            Some(message)
+                if message.hash != chunk.hash
+                    || message.chunks.len() != chunk.total_chunks as usize =>
+            {
+                return ChunkOutcome::Rejected("chunk disagrees with the rest of its message");
+            }
+            Some(_) => self.messages.get_mut(&key).unwrap(),
+            None => {
+                let partial = self
+                    .messages
+                    .keys()
+                    .filter(|(peer, _)| *peer == source)
+                    .count();
+                if partial >= MAX_PARTIAL_PER_PEER {
+                    return ChunkOutcome::Rejected("too many incomplete messages");
+                }
+                self.messages.entry(key).or_insert(PartialMessage {
+                    hash: chunk.hash,
+                    chunks: vec![None; chunk.total_chunks as usize],
+                    size: 0,
+                    last_progress: now,
+                    retries: 0,
+                })
+            }
+        };
+
+        let slot = &mut message.chunks[chunk.chunk_index as usize];
+        if slot.is_none() {
+            let size = chunk.data.len();
+            let peer_buffered = self.buffered.get(&source).copied().unwrap_or(0);
+            if message.size + size > MAX_MESSAGE_SIZE {
+                self.forget(key);
+                return ChunkOutcome::Rejected("message too large");
+            }
+            if peer_buffered + size > PEER_BUFFER || self.total_buffered + size > TOTAL_BUFFER {
+                // The chunk can still be requested again once memory frees up
+                return ChunkOutcome::Rejected("receive buffer full");
+            }
+
+            *slot = Some(chunk.data);
+            message.size += size;
+            message.last_progress = now;
+            *self.buffered.entry(source).or_default() += size;
+            self.total_buffered += size;
+        }
+        if message.chunks.iter().any(|chunk| chunk.is_none()) {
+            return ChunkOutcome::Pending;
+        }
+
+        let mut data = Vec::with_capacity(message.size);
+        for chunk in message.chunks.iter().flatten() {
+            data.extend_from_slice(chunk);
+        }
+        if <[u8; 32]>::from(Sha256::digest(&data)) != message.hash {
+            // Request everything again right away
+            message.chunks.fill(None);
+            message.last_progress = now.checked_sub(STALL_TIMEOUT).unwrap_or(now);
+            let size = std::mem::take(&mut message.size);
+            self.release(source, size);
+            return ChunkOutcome::Corrupt;
+        }
+
+        self.forget(key);
+        self.remember_completed(key, now);
+        ChunkOutcome::Complete(data)
+    }
+
+    /// Requests for the chunks of messages that stopped making progress.
+    pub fn stalled(&mut self, now: Instant) -> Vec<(PeerId, ChunkRequest)> {
+        let mut requests = vec![];
+        for ((source, id), message) in self.messages.iter_mut() {
+            if now.duration_since(message.last_progress) < STALL_TIMEOUT
+                || message.retries >= MAX_RETRIES
+            {
+                continue;
+            }
+            message.retries += 1;
+            message.last_progress = now;
+            requests.push((
+                *source,
+                ChunkRequest {
+                    id: *id,
+                    chunk_indices: message.missing(),
+                },
+            ));
+        }
+        requests
     }
 
-    pub fn add_chunk(&mut self, id: u64, chunk_index: u32, total_chunks: u32, data: Vec<u8>) {
-        // If it's a new message, initialize storage
-        self.chunk_sizes.entry(id).or_insert(total_chunks);
-        self.chunk_times.entry(id).or_insert(std::time::SystemTime::now());
-        let chunk_list = self
+    /// Chunks received so far and in total, for a message still being reassembled.
+    pub fn progress(&self, source: PeerId, id: u64) -> Option<(usize, usize)> {
+        let message = self.messages.get(&(source, id))?;
+        let received = message
             .chunks
-            .entry(id)
-            .or_insert_with(|| vec![None; total_chunks as usize]);
+            .iter()
+            .filter(|chunk| chunk.is_some())
+            .count();
+        Some((received, message.chunks.len()))
+    }
+
+    /// Drops messages that are still incomplete after every retry, and reports them.
+    pub fn expire(&mut self, now: Instant) -> Vec<TransferEvent> {
+        self.completed
+            .retain(|_, completed| now.duration_since(*completed) < COMPLETED_MEMORY);
+
+        let given_up: Vec<(PeerId, u64)> = self
+            .messages
+            .iter()
+            .filter(|(_, message)| {
+                message.retries >= MAX_RETRIES
+                    && now.duration_since(message.last_progress) >= STALL_TIMEOUT
+            })
+            .map(|(key, _)| *key)
+            .collect();
+
+        let mut failed = vec![];
+        for key in given_up {
+            let (source, id) = key;
+            if let Some(message) = self.forget(key) {
+                failed.push(TransferEvent::Failed {
+                    id,
+                    error: format!(
+                        "{} of {} chunks from {source} never arrived",
+                        message.missing().len(),
+                        message.chunks.len()
+                    ),
+                });
+            }
+        }
+        failed
+    }
 
-        // Store the chunk
-        chunk_list[chunk_index as usize] = Some(data);
+    fn forget(&mut self, key: (PeerId, u64)) -> Option<PartialMessage> {
+        let message = self.messages.remove(&key)?;
+        self.release(key.0, message.size);
+        Some(message)
     }
 
-    pub fn is_complete(&self, id: u64) -> bool {
-        if let Some(chunk_list) = self.chunks.get(&id) {
-            return chunk_list.iter().all(|chunk| chunk.is_some());
+    fn release(&mut self, source: PeerId, size: usize) {
+        self.total_buffered -= size;
+        if let Some(buffered) = self.buffered.get_mut(&source) {
+            *buffered -= size;
+            if *buffered == 0 {
+                self.buffered.remove(&source);
+            }
         }
-        false
     }
 
-    pub fn reassemble(&mut self, id: u64) -> Option<Vec<u8>> {
-        if self.is_complete(id) {
-            let chunk_list = self.chunks.get(&id).unwrap();
-            let mut message_data = Vec::new();
-            for chunk in chunk_list {
-                if let Some(data) = chunk {
-                    message_data.extend_from_slice(&data);
-                }
+    fn remember_completed(&mut self, key: (PeerId, u64), now: Instant) {
+        if self.completed.len() >= MAX_COMPLETED {
+            if let Some(oldest) = self
+                .completed
+                .iter()
+                .min_by_key(|(_, completed)| **completed)
+                .map(|(key, _)| *key)
+            {
+                self.completed.remove(&oldest);
             }
+        }
+        self.completed.insert(key, now);
+    }
+}
+
+// Messages are kept this long for retransmission, within a total size budget
+const SENT_MEMORY: Duration = Duration::from_secs(60);
+const SENT_BUDGET: usize = 64 * 1024 * 1024;
 
-            self.remove_chunks(&id);
-            return Some(message_data);
+/// Recently sent messages, kept to answer chunk requests.
+#[derive(Default)]
+pub struct SentMessages {
+    messages: VecDeque<(Instant, Vec<ChunkedMessage>)>,
+    size: usize,
+}
+
+impl SentMessages {
+    pub fn insert(&mut self, chunks: Vec<ChunkedMessage>) {
+        self.size += chunks.iter().map(|chunk| chunk.data.len()).sum::<usize>();
+        self.messages.push_back((Instant::now(), chunks));
+        self.expire(Instant::now());
+    }
+
+    pub fn chunks(&self, request: &ChunkRequest) -> Vec<ChunkedMessage> {
+        let Some((_, chunks)) = self
+            .messages
+            .iter()
+            .find(|(_, chunks)| chunks.first().is_some_and(|chunk| chunk.id == request.id))
+        else {
+            return vec![];
+        };
+        // Each chunk at most once, however often the request repeats an index
+        let requested: HashSet<u32> = request.chunk_indices.iter().copied().collect();
+        chunks
+            .iter()
+            .filter(|chunk| requested.contains(&chunk.chunk_index))
+            .cloned()
+            .collect()
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        while let Some((sent, chunks)) = self.messages.front() {
+            if now.duration_since(*sent) < SENT_MEMORY && self.size <= SENT_BUDGET {
+                break;
+            }
+            self.size -= chunks.iter().map(|chunk| chunk.data.len()).sum::<usize>();
+            self.messages.pop_front();
         }
-        None
     }
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum MessageType {
-    NewImage { bytes: Vec<u8> },
+    Operation { operation: Operation },
     CanvasState { state: SyncableState },
+    Playback { update: PlaybackUpdate },
+    JoinRequest { name: String }, // Asks the host for a role
+    Roster { roster: Roster },    // Only accepted from the host
+    CatchUpRequest { request: u64 },
+    CatchUp { request: u64, state: SyncableState }, // Answer from one of the peers
+}
+
+/// Commands sent from the GUI to the p2p thread.
+pub enum P2pCommand {
+    Broadcast(MessageType), // Sent to everyone in the current room
+    Presence(Presence),     // Dropped if it cannot be sent right away
+    JoinRoom {
+        room: Room,
+        passphrase: Option<String>, // Encrypts everything sent in the room
+    },
+    LeaveRoom,
+    Roster {
+        host: String, // Learned from announcements when joining by code
+        roster: Roster,
+    }, // Peers outside it are not sent images or missed chunks
+    Dial(Invite), // Also redialed whenever the peer is not connected
+    Forget(PeerId),
+}
+
+/// Events sent from the p2p thread to the GUI.
+pub enum P2pEvent {
+    LocalPeerId(String),
+    Message { from: String, message: MessageType }, // Peer ID of the author
+    BlobReceived(String), // Content hash of image bytes fetched from a peer
+    Transfer(TransferEvent),
+    RoomAnnounced(Room),
+    Decryption(OpenError),
+    Peers(Vec<PeerInfo>), // Whole list, sent whenever it changes
+    Presence { peer: String, presence: Presence },
+    NetworkError(String),
+    Invite(String), // Changes when the listen addresses do
+}
+
+/// Outcome of a chunked message transfer.
+pub enum TransferEvent {
+    Completed {
+        id: u64,
+        size: usize,
+    },
+    Progress {
+        id: u64,
+        received: usize,
+        total: usize,
+    }, // Chunks of a large message
+    Failed {
+        id: u64,
+        error: String,
+    },
+}
+
+impl MessageType {
+    /// Content hashes of the images a message refers to.
+    pub fn blob_hashes(&self) -> Vec<&str> {
+        match self {
+            MessageType::Operation { operation } => match &operation.kind {
+                OperationKind::Add { info, .. } => vec![info.content_hash.as_str()],
+                _ => vec![],
+            },
+            MessageType::CanvasState { state } | MessageType::CatchUp { state, .. } => state
+                .board
+                .ordered()
+                .into_iter()
+                .map(|(_, image)| image.info.value.content_hash.as_str())
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum PlaybackEvent {
+    Play,
+    Pause,
+    Seek { position_ms: u64 },
+    Rate { rate: f32 },
+}
+
+/// Where a peer is looking and pointing, in canvas space.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Presence {
+    pub name: String,
+    pub color: [u8; 3],
+    pub cursor: Option<[f32; 2]>, // None while the pointer is outside the window
+    pub viewport: [[f32; 2]; 2],  // Min and max corners
+    pub zoom: f32,
+    pub selection: Option<ItemId>,
+    pub presenting: bool,
+    pub following: Option<String>, // Peer ID of the followed peer
+}
+
+// Carries the resulting playback state alongside the event,
+// so a peer that missed earlier events still ends up in the same state.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlaybackUpdate {
+    pub image: ItemId,
+    pub event: PlaybackEvent,
+    pub stamp: Stamp, // From the board's clock, which catch-up snapshots carry to new peers
+    pub sent_at_ms: u64, // Sender's wall clock, only trusted within a bounded window
+    pub position_ms: u64,
+    pub playing: bool,
+    pub rate: f32,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -81,13 +499,99 @@ pub struct SyncableState {
     // TSTransfor does not derive Ser/Deser.
     // transform : Option<TSTransform>,
     // images: Vec<CanvasImageData>,
-    pub dropped_bytes: Vec<Vec<u8>>,
+    pub board: Board,
 }
 
 impl From<&App> for SyncableState {
     fn from(value: &App) -> Self {
         Self {
-            dropped_bytes: value.dropped_bytes.clone(),
+            board: value.board.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn chunk_strategy() -> impl Strategy<Value = (usize, ChunkedMessage)> {
+        (
+            0usize..3,
+            0u64..4,
+            any::<u8>(),
+            0u32..8,
+            prop_oneof![0u32..8, Just(u32::MAX)],
+            proptest::collection::vec(any::<u8>(), 0..64),
+        )
+            .prop_map(|(peer, id, hash, chunk_index, total_chunks, data)| {
+                (
+                    peer,
+                    ChunkedMessage {
+                        id,
+                        hash: [hash; 32],
+                        chunk_index,
+                        total_chunks,
+                        data,
+                    },
+                )
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn malformed_chunks_are_handled(chunks in proptest::collection::vec(chunk_strategy(), 0..200)) {
+            let peers = [PeerId::random(), PeerId::random(), PeerId::random()];
+            let mut collector = ChunkCollector::new();
+            for (peer, chunk) in chunks {
+                collector.add_chunk(peers[peer], chunk);
+                let buffered: usize = collector.messages.values().map(|message| message.size).sum();
+                prop_assert_eq!(collector.total_buffered, buffered);
+                prop_assert!(collector.total_buffered <= TOTAL_BUFFER);
+            }
+        }
+
+        #[test]
+        fn split_messages_reassemble(
+            message in proptest::collection::vec(any::<u8>(), 0..2000),
+            // At least 2 bytes per chunk keeps the count under MAX_TOTAL_CHUNKS
+            chunk_size in 2usize..500,
+            seed in any::<u64>(),
+        ) {
+            let peer = PeerId::random();
+            let mut chunks = split_message(&message, chunk_size);
+            // Any delivery order works
+            let rotate = seed as usize % chunks.len();
+            chunks.rotate_left(rotate);
+
+            let mut collector = ChunkCollector::new();
+            let last = chunks.pop().unwrap();
+            for chunk in chunks {
+                prop_assert!(matches!(collector.add_chunk(peer, chunk), ChunkOutcome::Pending));
+            }
+            let outcome = collector.add_chunk(peer, last.clone());
+            prop_assert!(matches!(outcome, ChunkOutcome::Complete(ref data) if *data == message));
+            prop_assert_eq!(collector.total_buffered, 0);
+
+            // Duplicates of a completed message are not delivered again
+            prop_assert!(matches!(collector.add_chunk(peer, last), ChunkOutcome::Pending));
+        }
+    }
+
+    #[test]
+    fn decoding_is_bounded() {
+        // A length prefix claiming a huge buffer fails instead of allocating it
+        let mut encoded = bincode::serialize(&ChunkedMessage {
+            id: 1,
+            hash: [0; 32],
+            chunk_index: 0,
+            total_chunks: 1,
+            data: vec![],
+        })
+        .unwrap();
+        let length_at = encoded.len() - 8;
+        encoded[length_at..].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(decode_chunk(&encoded).is_err());
+        assert!(decode_message(&[0xff; 16]).is_err());
+    }
+}
@@ -14,6 +14,7 @@ pub struct Coordinates {
 }
 
 impl CanvasImageData {
+    #[allow(dead_code)]
     pub fn new() -> Self {
         Self {
             ..Default::default()
@@ -66,3 +67,114 @@ fn canvas_image_component(ui: &mut egui::Ui, data: &mut CanvasImageData) -> egui
 pub fn canvas_image(data: &mut CanvasImageData) -> impl egui::Widget + '_ {
     move |ui: &mut egui::Ui| canvas_image_component(ui, data)
 }
+
+/// Composition and measurement overlays of a single image.
+pub struct ImageOverlays {
+    pub thirds: bool,
+    pub golden_ratio: bool,
+    pub center_lines: bool,
+    pub grid: bool,
+    pub grid_size: [u32; 2],          // Columns, rows
+    pub ruler: Option<[[f32; 2]; 2]>, // End points in source image pixels
+}
+
+impl Default for ImageOverlays {
+    fn default() -> Self {
+        Self {
+            thirds: false,
+            golden_ratio: false,
+            center_lines: false,
+            grid: false,
+            grid_size: [4, 4],
+            ruler: None,
+        }
+    }
+}
+
+const GOLDEN_SECTION: f32 = 0.381_966; // 1 - 1/phi
+
+fn overlay_image_ui(
+    ui: &mut egui::Ui,
+    image: egui::Image,
+    overlays: Option<&ImageOverlays>,
+    source_size: Option<[u32; 2]>,
+    scale: f32,
+) -> egui::Response {
+    let response = ui.add(image);
+    let Some(overlays) = overlays else {
+        return response;
+    };
+
+    let rect = response.rect;
+    let painter = ui.painter().with_clip_rect(rect);
+    // The layer is zoomed with the canvas. Keep the lines thin regardless of zoom.
+    let stroke = |color| egui::Stroke::new(1.0 / scale, color);
+
+    let mut fractions = vec![];
+    if overlays.thirds {
+        fractions.push((1.0 / 3.0, egui::Color32::from_white_alpha(160)));
+        fractions.push((2.0 / 3.0, egui::Color32::from_white_alpha(160)));
+    }
+    if overlays.golden_ratio {
+        let gold = egui::Color32::from_rgba_unmultiplied(255, 200, 0, 180);
+        fractions.push((GOLDEN_SECTION, gold));
+        fractions.push((1.0 - GOLDEN_SECTION, gold));
+    }
+    if overlays.center_lines {
+        fractions.push((0.5, egui::Color32::from_rgba_unmultiplied(0, 200, 255, 180)));
+    }
+    for (fraction, color) in fractions {
+        let x = egui::lerp(rect.x_range(), fraction);
+        let y = egui::lerp(rect.y_range(), fraction);
+        painter.vline(x, rect.y_range(), stroke(color));
+        painter.hline(rect.x_range(), y, stroke(color));
+    }
+
+    if overlays.grid {
+        let [columns, rows] = overlays.grid_size.map(|size| size.max(1));
+        let color = egui::Color32::from_black_alpha(160);
+        for column in 1..columns {
+            let x = egui::lerp(rect.x_range(), column as f32 / columns as f32);
+            painter.vline(x, rect.y_range(), stroke(color));
+        }
+        for row in 1..rows {
+            let y = egui::lerp(rect.y_range(), row as f32 / rows as f32);
+            painter.hline(rect.x_range(), y, stroke(color));
+        }
+    }
+
+    if let (Some([start, end]), Some([width, height])) = (overlays.ruler, source_size) {
+        let to_canvas = |[x, y]: [f32; 2]| {
+            rect.min + egui::vec2(x / width as f32, y / height as f32) * rect.size()
+        };
+        let (a, b) = (to_canvas(start), to_canvas(end));
+        let color = egui::Color32::from_rgb(255, 60, 160);
+        painter.line_segment([a, b], egui::Stroke::new(2.0 / scale, color));
+        painter.circle_filled(a, 3.0 / scale, color);
+        painter.circle_filled(b, 3.0 / scale, color);
+
+        let (dx, dy) = (end[0] - start[0], end[1] - start[1]);
+        let distance = dx.hypot(dy);
+        // Image y grows downwards. Angles are measured counter-clockwise, like on paper.
+        let angle = (-dy).atan2(dx).to_degrees();
+        painter.text(
+            a.lerp(b, 0.5) + egui::vec2(6.0, -6.0) / scale,
+            egui::Align2::LEFT_BOTTOM,
+            format!("{distance:.1} px  {angle:.1}°"),
+            egui::FontId::proportional(13.0 / scale),
+            color,
+        );
+    }
+
+    response
+}
+
+/// Image with optional composition overlays painted on top.
+pub fn overlay_image<'a>(
+    image: egui::Image<'a>,
+    overlays: Option<&'a ImageOverlays>,
+    source_size: Option<[u32; 2]>,
+    scale: f32,
+) -> impl egui::Widget + 'a {
+    move |ui: &mut egui::Ui| overlay_image_ui(ui, image, overlays, source_size, scale)
+}
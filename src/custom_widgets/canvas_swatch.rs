@@ -0,0 +1,45 @@
+use eframe::egui::{self, Color32};
+
+use crate::board::SwatchItem;
+
+const SWATCH_SIZE: f32 = 40.0;
+const SWATCH_SPACING: f32 = 2.0;
+const MARGIN: f32 = 4.0;
+
+fn canvas_swatch_ui(ui: &mut egui::Ui, item: &SwatchItem) -> egui::Response {
+    let count = item.colors.len().max(1) as f32;
+    let size = egui::vec2(
+        count * SWATCH_SIZE + (count - 1.0) * SWATCH_SPACING + 2.0 * MARGIN,
+        SWATCH_SIZE + 2.0 * MARGIN,
+    );
+    let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click_and_drag());
+
+    if ui.is_rect_visible(rect) {
+        let painter = ui.painter();
+        painter.rect_filled(rect, 4.0, Color32::from_gray(30));
+
+        for (index, [r, g, b]) in item.colors.iter().enumerate() {
+            painter.rect_filled(swatch_rect(rect, index), 2.0, Color32::from_rgb(*r, *g, *b));
+        }
+    }
+
+    response.on_hover_cursor(egui::CursorIcon::Grab)
+}
+
+fn swatch_rect(rect: egui::Rect, index: usize) -> egui::Rect {
+    let min = rect.min
+        + egui::vec2(
+            MARGIN + index as f32 * (SWATCH_SIZE + SWATCH_SPACING),
+            MARGIN,
+        );
+    egui::Rect::from_min_size(min, egui::Vec2::splat(SWATCH_SIZE))
+}
+
+/// Index of the colour under `position`, both in the widget's coordinates.
+pub fn swatch_index_at(item: &SwatchItem, rect: egui::Rect, position: egui::Pos2) -> Option<usize> {
+    (0..item.colors.len()).find(|index| swatch_rect(rect, *index).contains(position))
+}
+
+pub fn canvas_swatch(item: &SwatchItem) -> impl egui::Widget + '_ {
+    move |ui: &mut egui::Ui| canvas_swatch_ui(ui, item)
+}
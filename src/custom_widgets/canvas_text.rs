@@ -0,0 +1,52 @@
+use eframe::egui::{self, Color32};
+
+use crate::board::TextItem;
+
+fn canvas_text_ui(ui: &mut egui::Ui, item: &mut TextItem, editing: bool) -> egui::Response {
+    let [r, g, b, a] = item.color;
+    let color = Color32::from_rgba_unmultiplied(r, g, b, a);
+    let font = egui::FontId::proportional(item.font_size * item.scale);
+
+    let fill = item
+        .background
+        .map(|[r, g, b, a]| Color32::from_rgba_unmultiplied(r, g, b, a))
+        .unwrap_or(Color32::TRANSPARENT);
+
+    let frame = egui::Frame::none()
+        .fill(fill)
+        .rounding(4.0 * item.scale)
+        .inner_margin(6.0 * item.scale);
+
+    if editing {
+        return frame
+            .show(ui, |ui| {
+                let edit = ui.add(
+                    egui::TextEdit::multiline(&mut item.text)
+                        .font(font)
+                        .text_color(color)
+                        .frame(false)
+                        .desired_rows(1)
+                        .desired_width(f32::INFINITY),
+                );
+                edit.request_focus();
+                edit
+            })
+            .inner;
+    }
+
+    let response = frame
+        .show(ui, |ui| {
+            ui.add(
+                egui::Label::new(egui::RichText::new(&item.text).font(font).color(color))
+                    .selectable(false),
+            );
+        })
+        .response
+        .interact(egui::Sense::click_and_drag());
+
+    response.on_hover_cursor(egui::CursorIcon::Grab)
+}
+
+pub fn canvas_text(item: &mut TextItem, editing: bool) -> impl egui::Widget + '_ {
+    move |ui: &mut egui::Ui| canvas_text_ui(ui, item, editing)
+}
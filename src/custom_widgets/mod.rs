@@ -1,2 +1,4 @@
 pub mod canvas_image;
-pub mod toggle;
\ No newline at end of file
+pub mod canvas_swatch;
+pub mod canvas_text;
+pub mod toggle;
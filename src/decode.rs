@@ -0,0 +1,74 @@
+use anyhow::Result;
+use image::{metadata::Orientation, DynamicImage, ImageDecoder, ImageReader, RgbaImage};
+use std::{
+    io::Cursor,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+/// Decodes a still image the way it should look: EXIF orientation applied and
+/// colours converted from the embedded ICC profile to sRGB.
+/// Returns `None` if the image needs neither, so the original bytes can be shown as they are.
+pub fn decode_corrected(bytes: &[u8]) -> Result<Option<RgbaImage>> {
+    let mut decoder = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let icc_profile = decoder.icc_profile()?.filter(|icc| is_rgb_profile(icc));
+
+    if orientation == Orientation::NoTransforms && icc_profile.is_none() {
+        return Ok(None);
+    }
+
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    let mut image = image.into_rgba8();
+    if let Some(icc_profile) = icc_profile {
+        convert_to_srgb(&mut image, &icc_profile);
+    }
+
+    Ok(Some(image))
+}
+
+/// Decodes a still image with corrections applied, falling back to the plain pixels.
+pub fn decode_still(bytes: &[u8]) -> Result<RgbaImage> {
+    match decode_corrected(bytes)? {
+        Some(image) => Ok(image),
+        None => Ok(image::load_from_memory(bytes)?.to_rgba8()),
+    }
+}
+
+/// Decodes on a separate thread, see `decode_corrected`.
+pub fn spawn_decode_corrected(
+    bytes: Vec<u8>,
+) -> Receiver<std::result::Result<Option<RgbaImage>, String>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(decode_corrected(&bytes).map_err(|err| err.to_string()));
+    });
+    receiver
+}
+
+// Grey and CMYK profiles do not apply to the RGBA pixels the decoder hands out
+fn is_rgb_profile(icc_profile: &[u8]) -> bool {
+    icc_profile.get(16..20) == Some(b"RGB ")
+}
+
+fn convert_to_srgb(image: &mut RgbaImage, icc_profile: &[u8]) {
+    let Some(input) = qcms::Profile::new_from_slice(icc_profile, false) else {
+        println!("Ignoring unreadable ICC profile");
+        return;
+    };
+    let mut output = qcms::Profile::new_sRGB();
+    output.precache_output_transform();
+
+    match qcms::Transform::new(
+        &input,
+        &output,
+        qcms::DataType::RGBA8,
+        qcms::Intent::Perceptual,
+    ) {
+        Some(transform) => transform.apply(image),
+        None => println!("Ignoring unsupported ICC profile"),
+    }
+}
@@ -0,0 +1,115 @@
+use eframe::egui::{self, Color32, Pos2, Rect};
+use image::RgbaImage;
+use std::{
+    sync::{
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread,
+};
+
+use crate::{
+    frame_export::decode_frames,
+    palette::{color_descriptions, srgb_to_lab},
+};
+
+const LOUPE_PIXELS: i64 = 9; // Odd, so that the sampled pixel is in the middle
+const LOUPE_CELL: f32 = 10.0;
+pub const HISTORY_LENGTH: usize = 24;
+
+/// Decoded pixels of an image, one entry per animation frame.
+pub enum SourcePixels {
+    Loading(Receiver<std::result::Result<Vec<RgbaImage>, String>>),
+    Ready(Arc<Vec<RgbaImage>>),
+    Failed,
+}
+
+impl SourcePixels {
+    /// Decodes the source bytes on a separate thread.
+    pub fn decode(bytes: Vec<u8>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let frames = decode_frames(&bytes)
+                .map(|frames| frames.into_iter().map(|frame| frame.image).collect())
+                .map_err(|err| err.to_string());
+            let _ = sender.send(frames);
+        });
+        Self::Loading(receiver)
+    }
+
+    pub fn poll(&mut self) -> Option<Arc<Vec<RgbaImage>>> {
+        if let Self::Loading(receiver) = self {
+            match receiver.try_recv() {
+                Ok(Ok(frames)) => *self = Self::Ready(Arc::new(frames)),
+                Ok(Err(err)) => {
+                    println!("Failed to decode image for sampling: {err}");
+                    *self = Self::Failed;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => *self = Self::Failed,
+                Err(mpsc::TryRecvError::Empty) => {}
+            }
+        }
+
+        match self {
+            Self::Ready(frames) => Some(Arc::clone(frames)),
+            _ => None,
+        }
+    }
+}
+
+/// Maps a canvas position inside an image item onto a pixel of the source image.
+pub fn source_pixel(image: &RgbaImage, item_rect: Rect, position: Pos2) -> Option<(u32, u32)> {
+    let uv = (position - item_rect.min) / item_rect.size();
+    if !(0.0..1.0).contains(&uv.x) || !(0.0..1.0).contains(&uv.y) {
+        return None;
+    }
+
+    Some((
+        (uv.x * image.width() as f32) as u32,
+        (uv.y * image.height() as f32) as u32,
+    ))
+}
+
+/// Paints a magnified view of the pixels around `(x, y)` together with a colour readout.
+pub fn paint_loupe(painter: &egui::Painter, pointer: Pos2, image: &RgbaImage, (x, y): (u32, u32)) {
+    let size = LOUPE_PIXELS as f32 * LOUPE_CELL;
+    let origin = pointer + egui::vec2(20.0, 20.0);
+    let grid = Rect::from_min_size(origin, egui::Vec2::splat(size));
+
+    painter.rect_filled(grid.expand(2.0), 2.0, Color32::BLACK);
+
+    let half = LOUPE_PIXELS / 2;
+    for row in 0..LOUPE_PIXELS {
+        for column in 0..LOUPE_PIXELS {
+            let px = x as i64 + column - half;
+            let py = y as i64 + row - half;
+            let color =
+                if px < 0 || py < 0 || px >= image.width() as i64 || py >= image.height() as i64 {
+                    Color32::TRANSPARENT
+                } else {
+                    let [r, g, b, a] = image.get_pixel(px as u32, py as u32).0;
+                    Color32::from_rgba_unmultiplied(r, g, b, a)
+                };
+            let cell = Rect::from_min_size(
+                origin + egui::vec2(column as f32, row as f32) * LOUPE_CELL,
+                egui::Vec2::splat(LOUPE_CELL),
+            );
+            painter.rect_filled(cell, 0.0, color);
+        }
+    }
+
+    let center = Rect::from_min_size(
+        origin + egui::Vec2::splat(half as f32 * LOUPE_CELL),
+        egui::Vec2::splat(LOUPE_CELL),
+    );
+    painter.rect_stroke(center, 0.0, egui::Stroke::new(1.5, Color32::WHITE));
+
+    let [r, g, b, _] = image.get_pixel(x, y).0;
+    let mut readout = color_descriptions([r, g, b]).join("\n");
+    readout.push_str(&format!("\nvalue {:.0}", srgb_to_lab([r, g, b])[0]));
+
+    let galley = painter.layout_no_wrap(readout, egui::FontId::monospace(12.0), Color32::WHITE);
+    let text_rect = Rect::from_min_size(grid.left_bottom() + egui::vec2(0.0, 6.0), galley.size());
+    painter.rect_filled(text_rect.expand(4.0), 2.0, Color32::from_black_alpha(220));
+    painter.galley(text_rect.min, galley, Color32::WHITE);
+}
@@ -0,0 +1,228 @@
+use anyhow::{anyhow, Result};
+use image::{
+    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
+    AnimationDecoder, Frames, ImageFormat, RgbaImage,
+};
+use serde::Serialize;
+use std::{
+    io::Cursor,
+    ops::RangeInclusive,
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+use crate::decode::decode_still;
+
+// Larger sheets fail to load in most engines, and a big GIF could ask for gigabytes
+const MAX_SHEET_SIDE: u32 = 16384;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExportFormat {
+    PngSequence,
+    SpriteSheet,
+}
+
+pub struct ExportRequest {
+    pub bytes: Vec<u8>,
+    pub format: ExportFormat,
+    // In/out frame range. `None` exports every frame.
+    pub range: Option<RangeInclusive<usize>>,
+    pub output_dir: PathBuf,
+    pub name: String,
+}
+
+pub enum ExportEvent {
+    Progress { done: usize, total: usize },
+    Finished { path: PathBuf },
+    Failed { error: String },
+}
+
+pub struct DecodedFrame {
+    pub image: RgbaImage,
+    pub delay_ms: u32,
+}
+
+#[derive(Serialize)]
+struct SpriteAtlas {
+    image: String,
+    frame_width: u32,
+    frame_height: u32,
+    frames: Vec<AtlasFrame>,
+}
+
+#[derive(Serialize)]
+struct AtlasFrame {
+    index: usize,
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+    delay_ms: u32,
+}
+
+/// Runs the export on a separate thread. Progress is reported through the returned channel.
+pub fn spawn_export(request: ExportRequest) -> Receiver<ExportEvent> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let event = match export(request, &sender) {
+            Ok(path) => ExportEvent::Finished { path },
+            Err(err) => ExportEvent::Failed {
+                error: err.to_string(),
+            },
+        };
+        let _ = sender.send(event);
+    });
+
+    receiver
+}
+
+/// Decodes every frame of an animated GIF, APNG or WebP.
+/// Still images are returned as a single frame.
+pub fn decode_frames(bytes: &[u8]) -> Result<Vec<DecodedFrame>> {
+    let frames = match image::guess_format(bytes)? {
+        ImageFormat::Gif => collect_frames(GifDecoder::new(Cursor::new(bytes))?.into_frames())?,
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(Cursor::new(bytes))?;
+            if decoder.has_animation() {
+                collect_frames(decoder.into_frames())?
+            } else {
+                still_frame(bytes)?
+            }
+        }
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(Cursor::new(bytes))?;
+            if decoder.is_apng()? {
+                collect_frames(decoder.apng()?.into_frames())?
+            } else {
+                still_frame(bytes)?
+            }
+        }
+        _ => still_frame(bytes)?,
+    };
+
+    Ok(frames)
+}
+
+/// Whether an APNG or WebP has more than one frame, read from its headers only.
+/// GIF frame counts come from the egui loader instead.
+pub fn has_animation(bytes: &[u8]) -> bool {
+    match image::guess_format(bytes) {
+        Ok(ImageFormat::WebP) => {
+            WebPDecoder::new(Cursor::new(bytes)).is_ok_and(|decoder| decoder.has_animation())
+        }
+        Ok(ImageFormat::Png) => PngDecoder::new(Cursor::new(bytes))
+            .and_then(|decoder| decoder.is_apng())
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+fn collect_frames(frames: Frames) -> Result<Vec<DecodedFrame>> {
+    let mut decoded = vec![];
+    for frame in frames {
+        let frame = frame?;
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        decoded.push(DecodedFrame {
+            delay_ms: numer / denom.max(1),
+            image: frame.into_buffer(),
+        });
+    }
+    Ok(decoded)
+}
+
+fn still_frame(bytes: &[u8]) -> Result<Vec<DecodedFrame>> {
+    Ok(vec![DecodedFrame {
+        image: decode_still(bytes)?,
+        delay_ms: 0,
+    }])
+}
+
+fn export(request: ExportRequest, progress: &Sender<ExportEvent>) -> Result<PathBuf> {
+    let frames = decode_frames(&request.bytes)?;
+    if frames.is_empty() {
+        return Err(anyhow!("Image has no frames"));
+    }
+
+    let last = frames.len() - 1;
+    let range = request.range.unwrap_or(0..=last);
+    let (start, end) = (*range.start().min(&last), *range.end().min(&last));
+    if start > end {
+        return Err(anyhow!("In frame {start} is after out frame {end}"));
+    }
+    let frames = &frames[start..=end];
+
+    std::fs::create_dir_all(&request.output_dir)?;
+
+    match request.format {
+        ExportFormat::PngSequence => {
+            // Named by source frame, so a partial range lines up with a full export
+            let digits = end.to_string().len().max(3);
+            for (index, frame) in frames.iter().enumerate() {
+                let path = request.output_dir.join(format!(
+                    "{}_{:0digits$}.png",
+                    request.name,
+                    start + index
+                ));
+                frame.image.save_with_format(&path, ImageFormat::Png)?;
+                let _ = progress.send(ExportEvent::Progress {
+                    done: index + 1,
+                    total: frames.len(),
+                });
+            }
+            Ok(request.output_dir)
+        }
+        ExportFormat::SpriteSheet => {
+            // Animation decoders composite every frame onto the full canvas, so all cells share a size.
+            let (frame_width, frame_height) = frames[0].image.dimensions();
+            let columns = (frames.len() as f64).sqrt().ceil() as u32;
+            let rows = (frames.len() as u32).div_ceil(columns);
+
+            let (Some(width), Some(height)) = (
+                frame_width.checked_mul(columns),
+                frame_height.checked_mul(rows),
+            ) else {
+                return Err(anyhow!("Sprite sheet would be too large"));
+            };
+            if width > MAX_SHEET_SIDE || height > MAX_SHEET_SIDE {
+                return Err(anyhow!(
+                    "Sprite sheet would be {width}x{height} pixels, export fewer frames"
+                ));
+            }
+
+            let mut sheet = RgbaImage::new(width, height);
+            let mut atlas = SpriteAtlas {
+                image: format!("{}.png", request.name),
+                frame_width,
+                frame_height,
+                frames: vec![],
+            };
+
+            for (index, frame) in frames.iter().enumerate() {
+                let x = (index as u32 % columns) * frame_width;
+                let y = (index as u32 / columns) * frame_height;
+                image::imageops::replace(&mut sheet, &frame.image, x as i64, y as i64);
+                atlas.frames.push(AtlasFrame {
+                    index: start + index,
+                    x,
+                    y,
+                    w: frame_width,
+                    h: frame_height,
+                    delay_ms: frame.delay_ms,
+                });
+                let _ = progress.send(ExportEvent::Progress {
+                    done: index + 1,
+                    total: frames.len(),
+                });
+            }
+
+            let sheet_path = request.output_dir.join(&atlas.image);
+            sheet.save_with_format(&sheet_path, ImageFormat::Png)?;
+            let atlas_path = request.output_dir.join(format!("{}.json", request.name));
+            std::fs::write(atlas_path, serde_json::to_string_pretty(&atlas)?)?;
+
+            Ok(sheet_path)
+        }
+    }
+}
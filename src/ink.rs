@@ -0,0 +1,65 @@
+use eframe::egui::{self, Color32, Pos2, Rect};
+use std::collections::HashMap;
+
+use crate::board::{InkStroke, ItemId};
+
+/// Undoable ink edit.
+pub enum InkEdit {
+    Draw(InkStroke),
+    Erase(Vec<InkStroke>),
+}
+
+/// Resolves stroke points into canvas space.
+/// Returns `None` if the stroke is attached to an image that is not on the canvas.
+pub fn canvas_points(stroke: &InkStroke, image_rects: &HashMap<ItemId, Rect>) -> Option<Vec<Pos2>> {
+    let origin = match stroke.attached_to {
+        Some(image) => image_rects.get(&image)?.min.to_vec2(),
+        None => egui::Vec2::ZERO,
+    };
+
+    Some(
+        stroke
+            .points
+            .iter()
+            .map(|[x, y]| egui::pos2(*x, *y) + origin)
+            .collect(),
+    )
+}
+
+pub fn paint_stroke(painter: &egui::Painter, points: Vec<Pos2>, stroke: &InkStroke) {
+    let [r, g, b, a] = stroke.color;
+    let color = Color32::from_rgba_unmultiplied(r, g, b, a);
+    let stroke_width = stroke.width;
+
+    if let [point] = points[..] {
+        painter.circle_filled(point, stroke_width / 2.0, color);
+        return;
+    }
+
+    painter.add(egui::Shape::line(
+        points,
+        egui::Stroke::new(stroke_width, color),
+    ));
+}
+
+/// Checks if the polyline passes within `radius` of `position`.
+pub fn hits(points: &[Pos2], position: Pos2, radius: f32) -> bool {
+    if let [point] = points {
+        return point.distance(position) <= radius;
+    }
+
+    points
+        .windows(2)
+        .any(|segment| distance_to_segment(position, segment[0], segment[1]) <= radius)
+}
+
+fn distance_to_segment(point: Pos2, start: Pos2, end: Pos2) -> f32 {
+    let segment = end - start;
+    let length_sq = segment.length_sq();
+    if length_sq == 0.0 {
+        return point.distance(start);
+    }
+
+    let t = ((point - start).dot(segment) / length_sq).clamp(0.0, 1.0);
+    point.distance(start + segment * t)
+}
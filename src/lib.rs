@@ -1,9 +1,37 @@
-#[cfg(target_os = "android")]
+#[cfg(any(target_os = "android", fuzzing))]
+mod board;
+#[cfg(any(target_os = "android", fuzzing))]
 mod canvas_app;
-#[cfg(target_os = "android")]
-mod custom_widgets;
-#[cfg(target_os = "android")]
+#[cfg(any(target_os = "android", fuzzing))]
 mod canvas_state_sync;
+#[cfg(any(target_os = "android", fuzzing))]
+mod custom_widgets;
+#[cfg(any(target_os = "android", fuzzing))]
+mod decode;
+#[cfg(any(target_os = "android", fuzzing))]
+mod eyedropper;
+#[cfg(any(target_os = "android", fuzzing))]
+mod frame_export;
+#[cfg(any(target_os = "android", fuzzing))]
+mod ink;
+#[cfg(any(target_os = "android", fuzzing))]
+mod metadata;
+#[cfg(any(target_os = "android", fuzzing))]
+mod palette;
+#[cfg(any(target_os = "android", fuzzing))]
+mod playback;
+#[cfg(any(target_os = "android", fuzzing))]
+mod search;
+#[cfg(any(target_os = "android", fuzzing))]
+mod shapes;
+
+// Network message decoding, for the fuzz targets in `fuzz/`
+#[cfg(fuzzing)]
+pub mod fuzzing {
+    pub use crate::canvas_state_sync::sync_types::{
+        decode_chunk, decode_message, ChunkCollector, ChunkOutcome, MAX_MESSAGE_SIZE,
+    };
+}
 
 #[cfg(target_os = "android")]
 use eframe::{egui, NativeOptions};
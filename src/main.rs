@@ -1,10 +1,18 @@
 use eframe::egui::{self};
 
-use tracing_subscriber;
-
+mod board;
 mod canvas_app;
 mod custom_widgets;
 mod canvas_state_sync;
+mod decode;
+mod eyedropper;
+mod frame_export;
+mod ink;
+mod metadata;
+mod palette;
+mod playback;
+mod search;
+mod shapes;
 
 #[cfg(not(target_os = "android"))]
 fn main() -> eframe::Result {
@@ -0,0 +1,148 @@
+use exif::{In, Tag, Value};
+use sha2::{Digest, Sha256};
+
+/// The EXIF fields shown in the image inspector.
+#[derive(Clone, Default)]
+pub struct ExifSummary {
+    pub camera: Option<String>,
+    pub lens: Option<String>,
+    pub date: Option<String>,
+    pub orientation: Option<u32>,
+}
+
+/// Reads the EXIF block of a JPEG, PNG, WebP, TIFF or HEIF image.
+pub fn read_exif(bytes: &[u8]) -> Option<ExifSummary> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(bytes))
+        .ok()?;
+
+    let text = |tag| {
+        let field = exif.get_field(tag, In::PRIMARY)?;
+        match &field.value {
+            Value::Ascii(values) => values
+                .first()
+                .map(|value| String::from_utf8_lossy(value).trim().to_owned())
+                .filter(|value| !value.is_empty()),
+            _ => Some(field.display_value().to_string()),
+        }
+    };
+
+    // Many cameras repeat the make in the model name
+    let camera = match (text(Tag::Make), text(Tag::Model)) {
+        (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+        (Some(make), Some(model)) => Some(format!("{make} {model}")),
+        (make, model) => make.or(model),
+    };
+
+    Some(ExifSummary {
+        camera,
+        lens: text(Tag::LensModel),
+        date: text(Tag::DateTimeOriginal).or_else(|| text(Tag::DateTime)),
+        orientation: exif
+            .get_field(Tag::Orientation, In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0)),
+    })
+}
+
+pub fn orientation_name(orientation: u32) -> &'static str {
+    match orientation {
+        1 => "Normal",
+        2 => "Mirrored horizontally",
+        3 => "Rotated 180°",
+        4 => "Mirrored vertically",
+        5 => "Mirrored, rotated 90° counter-clockwise",
+        6 => "Rotated 90° clockwise",
+        7 => "Mirrored, rotated 90° clockwise",
+        8 => "Rotated 90° counter-clockwise",
+        _ => "Unknown",
+    }
+}
+
+/// SHA-256 of the image bytes, as lower case hex.
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+pub fn format_file_size(size: usize) -> String {
+    match size {
+        0..1024 => format!("{size} B"),
+        1024..1_048_576 => format!("{:.1} KiB", size as f64 / 1024.0),
+        _ => format!("{:.1} MiB", size as f64 / 1_048_576.0),
+    }
+}
+
+/// Formats a unix timestamp as `YYYY-MM-DD HH:MM UTC`.
+pub fn format_utc(timestamp_ms: u64) -> String {
+    let seconds = timestamp_ms / 1000;
+    let (days, seconds_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // Civil date from days since 1970-01-01, after Howard Hinnant's days_from_civil inverse
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02} UTC",
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(seconds: u64) -> String {
+        format_utc(seconds * 1000)
+    }
+
+    #[test]
+    fn epoch() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_utc(59_999), "1970-01-01 00:00 UTC");
+        assert_eq!(utc(86_399), "1970-01-01 23:59 UTC");
+        assert_eq!(utc(86_400), "1970-01-02 00:00 UTC");
+    }
+
+    #[test]
+    fn leap_years() {
+        assert_eq!(utc(951_782_400), "2000-02-29 00:00 UTC"); // Divisible by 400
+        assert_eq!(utc(951_868_800), "2000-03-01 00:00 UTC");
+        assert_eq!(utc(1_709_164_800), "2024-02-29 00:00 UTC");
+        assert_eq!(utc(4_107_542_400), "2100-03-01 00:00 UTC"); // Divisible by 100, not a leap year
+        assert_eq!(utc(4_107_456_000), "2100-02-28 00:00 UTC");
+    }
+
+    #[test]
+    fn year_boundaries() {
+        assert_eq!(utc(1_704_067_199), "2023-12-31 23:59 UTC");
+        assert_eq!(utc(1_704_067_200), "2024-01-01 00:00 UTC");
+        assert_eq!(utc(1_735_689_599), "2024-12-31 23:59 UTC");
+    }
+
+    #[test]
+    fn file_sizes() {
+        assert_eq!(format_file_size(1023), "1023 B");
+        assert_eq!(format_file_size(1536), "1.5 KiB");
+        assert_eq!(format_file_size(3 * 1_048_576), "3.0 MiB");
+    }
+
+    #[test]
+    fn content_hash_is_sha256_hex() {
+        assert_eq!(
+            content_hash(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
@@ -0,0 +1,297 @@
+use anyhow::{anyhow, Result};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use crate::decode::decode_still;
+
+// Images are downscaled before clustering. A few thousand samples are plenty for dominant colours.
+const SAMPLE_SIZE: u32 = 96;
+const ITERATIONS: usize = 20;
+
+/// Extracts the palette on a separate thread.
+pub fn spawn_extract_palette(
+    bytes: Vec<u8>,
+    count: usize,
+) -> Receiver<std::result::Result<Vec<[u8; 3]>, String>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(extract_palette(&bytes, count).map_err(|err| err.to_string()));
+    });
+    receiver
+}
+
+/// Dominant colours of an image, most common first.
+/// Pixels are clustered with k-means in CIELAB space, where distances follow perceived difference.
+pub fn extract_palette(bytes: &[u8], count: usize) -> Result<Vec<[u8; 3]>> {
+    let image = image::DynamicImage::from(decode_still(bytes)?)
+        .thumbnail(SAMPLE_SIZE, SAMPLE_SIZE)
+        .to_rgba8();
+
+    let samples: Vec<[f32; 3]> = image
+        .pixels()
+        .filter(|pixel| pixel[3] >= 128)
+        .map(|pixel| srgb_to_lab([pixel[0], pixel[1], pixel[2]]))
+        .collect();
+
+    if samples.is_empty() {
+        return Err(anyhow!("Image has no opaque pixels"));
+    }
+
+    let mut clusters = kmeans(&samples, count.max(1));
+    clusters.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+
+    Ok(clusters
+        .into_iter()
+        .map(|(center, _)| lab_to_srgb(center))
+        .collect())
+}
+
+/// Returns the cluster centres together with their sizes.
+fn kmeans(samples: &[[f32; 3]], count: usize) -> Vec<([f32; 3], usize)> {
+    // Fixed seed, so extracting the same image twice gives the same palette.
+    let mut rng = StdRng::seed_from_u64(0);
+
+    // k-means++ initialisation: spread the initial centres out.
+    let mut centers = vec![samples[rng.gen_range(0..samples.len())]];
+    while centers.len() < count {
+        let distances: Vec<f32> = samples
+            .iter()
+            .map(|sample| nearest(&centers, sample).1)
+            .collect();
+        let total: f32 = distances.iter().sum();
+        if total == 0.0 {
+            break; // Fewer distinct colours than requested
+        }
+
+        let mut target = rng.gen_range(0.0..total);
+        let index = distances
+            .iter()
+            .position(|distance| {
+                target -= distance;
+                target <= 0.0
+            })
+            .unwrap_or(samples.len() - 1);
+        centers.push(samples[index]);
+    }
+
+    let mut sizes = vec![0; centers.len()];
+    for _ in 0..ITERATIONS {
+        let mut sums = vec![[0.0f32; 3]; centers.len()];
+        sizes = vec![0; centers.len()];
+
+        for sample in samples {
+            let (cluster, _) = nearest(&centers, sample);
+            for channel in 0..3 {
+                sums[cluster][channel] += sample[channel];
+            }
+            sizes[cluster] += 1;
+        }
+
+        for (cluster, center) in centers.iter_mut().enumerate() {
+            if sizes[cluster] > 0 {
+                *center = sums[cluster].map(|sum| sum / sizes[cluster] as f32);
+            }
+        }
+    }
+
+    centers
+        .into_iter()
+        .zip(sizes)
+        .filter(|(_, size)| *size > 0)
+        .collect()
+}
+
+/// Index of the nearest centre and the squared distance to it.
+fn nearest(centers: &[[f32; 3]], sample: &[f32; 3]) -> (usize, f32) {
+    centers
+        .iter()
+        .map(|center| (0..3).map(|c| (center[c] - sample[c]).powi(2)).sum::<f32>())
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or((0, 0.0))
+}
+
+// D65 reference white
+const WHITE: [f32; 3] = [0.95047, 1.0, 1.08883];
+
+pub fn srgb_to_lab(rgb: [u8; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb.map(|c| srgb_to_linear(c as f32 / 255.0));
+
+    let xyz = [
+        0.4124 * r + 0.3576 * g + 0.1805 * b,
+        0.2126 * r + 0.7152 * g + 0.0722 * b,
+        0.0193 * r + 0.1192 * g + 0.9505 * b,
+    ];
+    let [fx, fy, fz] = [0, 1, 2].map(|i| lab_f(xyz[i] / WHITE[i]));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+pub fn lab_to_srgb(lab: [f32; 3]) -> [u8; 3] {
+    let fy = (lab[0] + 16.0) / 116.0;
+    let fx = fy + lab[1] / 500.0;
+    let fz = fy - lab[2] / 200.0;
+    let [x, y, z] = [fx, fy, fz].map(lab_f_inverse);
+    let (x, y, z) = (x * WHITE[0], y * WHITE[1], z * WHITE[2]);
+
+    [
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    ]
+    .map(|c| (linear_to_srgb(c.clamp(0.0, 1.0)) * 255.0).round() as u8)
+}
+
+fn lab_f(t: f32) -> f32 {
+    if t > 0.008856 {
+        t.cbrt()
+    } else {
+        7.787 * t + 16.0 / 116.0
+    }
+}
+
+fn lab_f_inverse(t: f32) -> f32 {
+    if t.powi(3) > 0.008856 {
+        t.powi(3)
+    } else {
+        (t - 16.0 / 116.0) / 7.787
+    }
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Hue in degrees, saturation and value in percent.
+pub fn rgb_to_hsv(rgb: [u8; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb.map(|c| c as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+
+    [hue, saturation * 100.0, max * 100.0]
+}
+
+pub fn hex(rgb: [u8; 3]) -> String {
+    format!("#{:02X}{:02X}{:02X}", rgb[0], rgb[1], rgb[2])
+}
+
+/// Text representations of a colour, as offered for copying.
+pub fn color_descriptions(rgb: [u8; 3]) -> [String; 3] {
+    let [h, s, v] = rgb_to_hsv(rgb);
+    [
+        hex(rgb),
+        format!("rgb({}, {}, {})", rgb[0], rgb[1], rgb[2]),
+        format!("hsv({h:.0}, {s:.0}%, {v:.0}%)"),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgba, RgbaImage};
+    use std::io::Cursor;
+
+    #[test]
+    fn lab_round_trips() {
+        for r in (0..=255).step_by(15) {
+            for g in (0..=255).step_by(15) {
+                for b in (0..=255).step_by(15) {
+                    let rgb = [r as u8, g as u8, b as u8];
+                    let back = lab_to_srgb(srgb_to_lab(rgb));
+                    for channel in 0..3 {
+                        assert!(
+                            rgb[channel].abs_diff(back[channel]) <= 1,
+                            "{rgb:?} came back as {back:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lab_matches_reference_colours() {
+        let white = srgb_to_lab([255, 255, 255]);
+        assert!((white[0] - 100.0).abs() < 0.1 && white[1].abs() < 0.1 && white[2].abs() < 0.1);
+        assert_eq!(srgb_to_lab([0, 0, 0]), [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn kmeans_finds_separate_clusters() {
+        let red = srgb_to_lab([255, 0, 0]);
+        let blue = srgb_to_lab([0, 0, 255]);
+        let mut samples = vec![red; 30];
+        samples.extend([blue; 10]);
+
+        let mut clusters = kmeans(&samples, 2);
+        clusters.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(
+            (lab_to_srgb(clusters[0].0), clusters[0].1),
+            ([255, 0, 0], 30)
+        );
+        assert_eq!(
+            (lab_to_srgb(clusters[1].0), clusters[1].1),
+            ([0, 0, 255], 10)
+        );
+    }
+
+    #[test]
+    fn fewer_colours_than_requested() {
+        let samples = [srgb_to_lab([0, 128, 0]), srgb_to_lab([255, 255, 0])].repeat(5);
+        assert_eq!(kmeans(&samples, 6).len(), 2);
+    }
+
+    #[test]
+    fn palette_is_most_common_first() {
+        // Already at the sample size, so no pixels are blended
+        let mut image = RgbaImage::from_pixel(SAMPLE_SIZE, SAMPLE_SIZE, Rgba([0, 128, 0, 255]));
+        for x in 0..SAMPLE_SIZE {
+            image.put_pixel(x, 0, Rgba([255, 255, 0, 255]));
+            image.put_pixel(x, 1, Rgba([0, 0, 0, 0])); // Transparent pixels are skipped
+        }
+        let mut bytes = vec![];
+        image
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+
+        let palette = extract_palette(&bytes, 4).unwrap();
+        assert_eq!(palette, vec![[0, 128, 0], [255, 255, 0]]);
+    }
+
+    #[test]
+    fn colour_descriptions() {
+        assert_eq!(
+            color_descriptions([255, 128, 0]),
+            ["#FF8000", "rgb(255, 128, 0)", "hsv(30, 100%, 100%)"]
+        );
+        assert_eq!(rgb_to_hsv([0, 0, 0]), [0.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,229 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    board::ItemId,
+    canvas_state_sync::{
+        crdt::Stamp,
+        sync_types::{PlaybackEvent, PlaybackUpdate},
+    },
+};
+
+// Peers' clocks are not synchronised, so the transit time derived from `sent_at_ms`
+// can be wildly off. Compensation is capped to keep skewed clocks from causing jumps.
+const MAX_LATENCY_COMPENSATION_MS: u64 = 500;
+
+/// Playback clock of a single animated item.
+/// The media position is stored at an anchor time and extrapolated from there.
+pub struct Playback {
+    pub playing: bool,
+    pub rate: f32,
+    anchor_position_ms: f64,
+    anchor_time: f64,
+    // Last applied change. Used to discard stale and out of order updates.
+    stamp: Stamp,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            playing: true,
+            rate: 1.0,
+            anchor_position_ms: 0.0,
+            anchor_time: 0.0,
+            stamp: Stamp::default(),
+        }
+    }
+}
+
+impl Playback {
+    /// Media position in milliseconds. `now` is the egui time in seconds.
+    pub fn position_ms(&self, now: f64) -> f64 {
+        if self.playing {
+            self.anchor_position_ms + (now - self.anchor_time) * 1000.0 * self.rate as f64
+        } else {
+            self.anchor_position_ms
+        }
+    }
+
+    /// Applies a local event and returns the update that should be sent to peers.
+    /// `stamp` comes from `Board::tick`, so it is later than every update seen so far.
+    pub fn apply_local(
+        &mut self,
+        event: PlaybackEvent,
+        now: f64,
+        image: ItemId,
+        stamp: Stamp,
+    ) -> PlaybackUpdate {
+        self.apply_event(event, now);
+        self.stamp = stamp;
+
+        PlaybackUpdate {
+            image,
+            event,
+            stamp,
+            sent_at_ms: unix_time_ms(),
+            position_ms: self.anchor_position_ms.max(0.0) as u64,
+            playing: self.playing,
+            rate: self.rate,
+        }
+    }
+
+    /// Applies an update received from a peer. Returns false if the update was stale.
+    pub fn apply_remote(&mut self, update: &PlaybackUpdate, now: f64) -> bool {
+        if update.stamp <= self.stamp {
+            return false;
+        }
+
+        self.stamp = update.stamp;
+        self.playing = update.playing;
+        self.rate = update.rate;
+        self.anchor_time = now;
+        self.anchor_position_ms = update.position_ms as f64;
+
+        if update.playing {
+            let transit_ms = unix_time_ms()
+                .saturating_sub(update.sent_at_ms)
+                .min(MAX_LATENCY_COMPENSATION_MS);
+            self.anchor_position_ms += transit_ms as f64 * update.rate as f64;
+        }
+
+        true
+    }
+
+    fn apply_event(&mut self, event: PlaybackEvent, now: f64) {
+        // Re-anchor first, so that the position is continuous across the change.
+        self.anchor_position_ms = self.position_ms(now);
+        self.anchor_time = now;
+
+        match event {
+            PlaybackEvent::Play => self.playing = true,
+            PlaybackEvent::Pause => self.playing = false,
+            PlaybackEvent::Seek { position_ms } => self.anchor_position_ms = position_ms as f64,
+            PlaybackEvent::Rate { rate } => self.rate = rate,
+        }
+    }
+}
+
+/// Returns the frame shown at `position_ms` and how long until the next frame.
+/// The animation loops, so the position is wrapped around the total duration.
+pub fn frame_at(durations: &[Duration], position_ms: f64) -> (usize, Duration) {
+    let total_ms: u128 = durations.iter().map(Duration::as_millis).sum();
+    if total_ms == 0 {
+        return (0, Duration::MAX);
+    }
+
+    let position_ms = (position_ms.max(0.0) as u128) % total_ms;
+    let mut cumulative_ms = 0;
+    for (index, duration) in durations.iter().enumerate() {
+        cumulative_ms += duration.as_millis();
+        if position_ms < cumulative_ms {
+            return (
+                index,
+                Duration::from_millis((cumulative_ms - position_ms) as u64),
+            );
+        }
+    }
+
+    (0, Duration::MAX)
+}
+
+/// Media position at which the given frame starts.
+pub fn frame_start_ms(durations: &[Duration], frame: usize) -> u64 {
+    durations
+        .iter()
+        .take(frame)
+        .map(|duration| duration.as_millis() as u64)
+        .sum()
+}
+
+pub fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas_state_sync::crdt::Board;
+
+    fn pause_at(stamp: Stamp, position_ms: u64) -> PlaybackUpdate {
+        PlaybackUpdate {
+            image: 1,
+            event: PlaybackEvent::Pause,
+            stamp,
+            sent_at_ms: unix_time_ms(),
+            position_ms,
+            playing: false,
+            rate: 1.0,
+        }
+    }
+
+    fn stamp(counter: u64, actor: u64) -> Stamp {
+        Stamp { counter, actor }
+    }
+
+    #[test]
+    fn stale_updates_are_discarded() {
+        let mut playback = Playback::default();
+        assert!(playback.apply_remote(&pause_at(stamp(5, 1), 500), 0.0));
+        assert!(!playback.apply_remote(&pause_at(stamp(4, 2), 400), 0.0));
+        assert!(!playback.apply_remote(&pause_at(stamp(5, 1), 400), 0.0));
+        assert_eq!(playback.position_ms(10.0), 500.0);
+    }
+
+    #[test]
+    fn concurrent_updates_converge() {
+        let first = pause_at(stamp(3, 1), 100);
+        let second = pause_at(stamp(3, 2), 200);
+
+        let mut a = Playback::default();
+        a.apply_remote(&first, 0.0);
+        a.apply_remote(&second, 0.0);
+        let mut b = Playback::default();
+        b.apply_remote(&second, 0.0);
+        b.apply_remote(&first, 0.0);
+
+        assert_eq!(a.position_ms(1.0), 200.0);
+        assert_eq!(b.position_ms(1.0), 200.0);
+    }
+
+    #[test]
+    fn late_joiner_updates_are_accepted() {
+        let mut host_board = Board::default();
+        let mut host = Playback::default();
+        for position_ms in [100, 200, 300] {
+            let event = PlaybackEvent::Seek { position_ms };
+            host.apply_local(event, 0.0, 1, host_board.tick(1));
+        }
+
+        // The catch-up snapshot brings the clock along
+        let mut joiner_board = Board::default();
+        joiner_board.merge(&host_board);
+        let mut joiner = Playback::default();
+        let update = joiner.apply_local(PlaybackEvent::Pause, 0.0, 1, joiner_board.tick(2));
+
+        assert!(host.apply_remote(&update, 0.0));
+        assert!(!host.playing);
+    }
+
+    #[test]
+    fn frames_wrap_around() {
+        let durations = [Duration::from_millis(100), Duration::from_millis(50)];
+        assert_eq!(frame_at(&durations, 0.0), (0, Duration::from_millis(100)));
+        assert_eq!(frame_at(&durations, 120.0), (1, Duration::from_millis(30)));
+        assert_eq!(frame_at(&durations, 150.0), (0, Duration::from_millis(100)));
+        assert_eq!(frame_at(&durations, 930.0), (0, Duration::from_millis(70)));
+        assert_eq!(frame_at(&durations, -10.0), (0, Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn zero_length_frames_are_skipped() {
+        let durations = [Duration::ZERO, Duration::from_millis(100), Duration::ZERO];
+        assert_eq!(frame_at(&durations, 0.0), (1, Duration::from_millis(100)));
+        assert_eq!(frame_at(&durations, 99.0), (1, Duration::from_millis(1)));
+        assert_eq!(frame_at(&[Duration::ZERO; 3], 50.0), (0, Duration::MAX));
+        assert_eq!(frame_at(&[], 50.0), (0, Duration::MAX));
+    }
+}
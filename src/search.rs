@@ -0,0 +1,202 @@
+use crate::board::{ItemId, ItemLabels};
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum SearchMode {
+    #[default]
+    Highlight, // Matches are outlined, everything else is dimmed
+    Filter, // Only matches are shown
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SearchHit {
+    Image(ItemId),
+    Text(ItemId),
+    Swatch(ItemId),
+}
+
+#[derive(Default)]
+pub struct Search {
+    pub query: String,
+    pub mode: SearchMode,
+    pub hits: Vec<SearchHit>,
+    pub current: Option<usize>, // Index into `hits` of the last result zoomed to
+    pub zoom_to: Option<SearchHit>,
+}
+
+impl Search {
+    pub fn is_active(&self) -> bool {
+        !self.query.trim().is_empty()
+    }
+
+    /// Whether an item is shown normally. Always true while nothing is searched for.
+    pub fn is_match(&self, hit: SearchHit) -> bool {
+        !self.is_active() || self.hits.contains(&hit)
+    }
+
+    /// Moves on to the next result and requests zooming to it.
+    pub fn zoom_to_next(&mut self) {
+        if self.hits.is_empty() {
+            return;
+        }
+        let next = self
+            .current
+            .map_or(0, |current| (current + 1) % self.hits.len());
+        self.current = Some(next);
+        self.zoom_to = Some(self.hits[next]);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Tag,
+    Name,
+    Path,
+    Format,
+}
+
+/// Parsed search query. Terms are separated by whitespace and must all match.
+/// A term matches any field, unless prefixed with `tag:`, `name:`, `path:` or `format:`.
+pub struct Query {
+    terms: Vec<(Option<Field>, String)>,
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Self {
+        let terms = query
+            .split_whitespace()
+            .map(|term| {
+                let term = term.to_lowercase();
+                let field = match term.split_once(':') {
+                    Some(("tag", value)) => Some((Field::Tag, value)),
+                    Some(("name", value)) => Some((Field::Name, value)),
+                    Some(("path", value)) => Some((Field::Path, value)),
+                    Some(("format", value)) => Some((Field::Format, value)),
+                    _ => None,
+                };
+                match field {
+                    Some((field, value)) => (Some(field), value.to_owned()),
+                    None => (None, term),
+                }
+            })
+            .filter(|(_, value)| !value.is_empty())
+            .collect();
+        Self { terms }
+    }
+}
+
+/// Searchable fields of a board item.
+pub struct Searchable<'a> {
+    pub labels: &'a ItemLabels,
+    pub name: Option<&'a str>,
+    pub path: Option<&'a str>,
+    pub format: Option<&'a str>,
+    pub text: Option<&'a str>, // Content of text items
+}
+
+impl Searchable<'_> {
+    pub fn matches(&self, query: &Query) -> bool {
+        query.terms.iter().all(|(field, value)| {
+            let contains = |field: Option<&str>| {
+                field.is_some_and(|field| field.to_lowercase().contains(value.as_str()))
+            };
+            let tag = || {
+                self.labels
+                    .tags
+                    .iter()
+                    .any(|tag| tag.to_lowercase().contains(value.as_str()))
+            };
+
+            match field {
+                Some(Field::Tag) => tag(),
+                Some(Field::Name) => contains(self.name),
+                Some(Field::Path) => contains(self.path),
+                Some(Field::Format) => contains(self.format),
+                None => {
+                    tag()
+                        || contains(Some(&self.labels.description))
+                        || contains(self.name)
+                        || contains(self.path)
+                        || contains(self.format)
+                        || contains(self.text)
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(tags: &[&str], description: &str) -> ItemLabels {
+        ItemLabels {
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            description: description.to_owned(),
+        }
+    }
+
+    fn image<'a>(labels: &'a ItemLabels, name: &'a str, format: &'a str) -> Searchable<'a> {
+        Searchable {
+            labels,
+            name: Some(name),
+            path: Some(name),
+            format: Some(format),
+            text: None,
+        }
+    }
+
+    fn matches(item: &Searchable, query: &str) -> bool {
+        item.matches(&Query::parse(query))
+    }
+
+    #[test]
+    fn fields_are_matched_by_prefix() {
+        let labels = labels(&["Reference", "hands"], "Pose study");
+        let item = image(&labels, "sketches/hand_03.png", "PNG");
+
+        assert!(matches(&item, "tag:hand"));
+        assert!(matches(&item, "TAG:reference"));
+        assert!(!matches(&item, "tag:pose"));
+        assert!(matches(&item, "format:png"));
+        assert!(!matches(&item, "format:gif"));
+        assert!(matches(&item, "path:sketches/"));
+        assert!(matches(&item, "name:hand_03"));
+        assert!(!matches(&item, "path:study"));
+    }
+
+    #[test]
+    fn plain_terms_match_any_field() {
+        let labels = labels(&["hands"], "Pose study");
+        let item = image(&labels, "hand_03.png", "PNG");
+
+        assert!(matches(&item, "study"));
+        assert!(matches(&item, "png hands"));
+        assert!(!matches(&item, "hands feet"));
+        assert!(!matches(&item, "unknown:term"));
+    }
+
+    #[test]
+    fn text_items_match_their_content() {
+        let labels = ItemLabels::default();
+        let item = Searchable {
+            labels: &labels,
+            name: None,
+            path: None,
+            format: None,
+            text: Some("Lighting notes"),
+        };
+
+        assert!(matches(&item, "lighting"));
+        assert!(!matches(&item, "format:lighting"));
+    }
+
+    #[test]
+    fn empty_terms_match_everything() {
+        let labels = ItemLabels::default();
+        let item = image(&labels, "a.png", "PNG");
+
+        assert!(matches(&item, ""));
+        assert!(matches(&item, "   "));
+        assert!(matches(&item, "tag: format:"));
+    }
+}
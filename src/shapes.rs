@@ -0,0 +1,99 @@
+use eframe::egui::{self, Color32, Pos2, Rect};
+use std::collections::HashMap;
+
+use crate::board::{ItemId, ShapeAnchor, ShapeItem, ShapeKind};
+
+/// Canvas space rects of the items that shapes can attach to.
+pub struct AnchorTargets<'a> {
+    pub images: &'a HashMap<ItemId, Rect>,
+    pub texts: &'a HashMap<ItemId, Rect>,
+}
+
+impl AnchorTargets<'_> {
+    fn rect(&self, anchor: &ShapeAnchor) -> Option<Rect> {
+        match anchor {
+            ShapeAnchor::Point(_) => None,
+            ShapeAnchor::Image(image) => self.images.get(image).copied(),
+            ShapeAnchor::Text(text) => self.texts.get(text).copied(),
+        }
+    }
+
+    fn point(&self, anchor: &ShapeAnchor) -> Option<Pos2> {
+        match anchor {
+            ShapeAnchor::Point([x, y]) => Some(egui::pos2(*x, *y)),
+            _ => self.rect(anchor).map(|rect| rect.center()),
+        }
+    }
+}
+
+/// Resolves the end points of a shape into canvas space.
+/// Attached end points are moved onto the edge of their item, so arrow heads stay visible.
+/// Returns `None` if an attached item is not on the canvas.
+pub fn resolve_endpoints(shape: &ShapeItem, targets: &AnchorTargets) -> Option<(Pos2, Pos2)> {
+    let start = targets.point(&shape.start)?;
+    let end = targets.point(&shape.end)?;
+
+    let clipped_start = targets
+        .rect(&shape.start)
+        .map_or(start, |rect| clip_to_edge(rect, end));
+    let clipped_end = targets
+        .rect(&shape.end)
+        .map_or(end, |rect| clip_to_edge(rect, start));
+
+    Some((clipped_start, clipped_end))
+}
+
+/// Point where the line from the centre of `rect` towards `target` leaves the rect.
+fn clip_to_edge(rect: Rect, target: Pos2) -> Pos2 {
+    let center = rect.center();
+    let direction = target - center;
+    let half = rect.size() / 2.0;
+
+    let tx = if direction.x == 0.0 {
+        f32::INFINITY
+    } else {
+        half.x / direction.x.abs()
+    };
+    let ty = if direction.y == 0.0 {
+        f32::INFINITY
+    } else {
+        half.y / direction.y.abs()
+    };
+
+    center + direction * tx.min(ty).min(1.0)
+}
+
+/// Paints a shape. `start` and `end` are in canvas space, the layer transform does the zoom.
+pub fn paint_shape(painter: &egui::Painter, shape: &ShapeItem, start: Pos2, end: Pos2) {
+    let [r, g, b, a] = shape.color;
+    let stroke = egui::Stroke::new(shape.width, Color32::from_rgba_unmultiplied(r, g, b, a));
+
+    match shape.kind {
+        ShapeKind::Line => {
+            painter.line_segment([start, end], stroke);
+        }
+        ShapeKind::Arrow => {
+            painter.line_segment([start, end], stroke);
+
+            let direction = (end - start).normalized();
+            let head_length = (shape.width * 4.0).max(10.0);
+            let rotation = egui::emath::Rot2::from_angle(std::f32::consts::PI / 7.0);
+            painter.line_segment([end, end - rotation * direction * head_length], stroke);
+            painter.line_segment(
+                [end, end - rotation.inverse() * direction * head_length],
+                stroke,
+            );
+        }
+        ShapeKind::Rectangle => {
+            painter.rect_stroke(Rect::from_two_pos(start, end), 0.0, stroke);
+        }
+        ShapeKind::Ellipse => {
+            let rect = Rect::from_two_pos(start, end);
+            painter.add(egui::Shape::ellipse_stroke(
+                rect.center(),
+                rect.size() / 2.0,
+                stroke,
+            ));
+        }
+    }
+}